@@ -1,29 +1,99 @@
-#[macro_use]
-extern crate prettytable;
-
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
 use std::env;
+use std::sync::{Arc, Mutex};
 use utils::print_pretty_header;
 
 mod utils;
 
+use crate::utils::commands::CommandCompleter;
+use crate::utils::completion::{EngineCompleter, LocalCompleter, SessionState};
 use crate::utils::conf::ByzerConf;
-use crate::utils::progress_bar::ExecutingProgressBar;
-use crate::utils::{run_loop, run_script};
+use crate::utils::engine::EngineClient;
+use crate::utils::external_editor::ExternalEditor;
+use crate::utils::formatter;
+use crate::utils::history::History;
+use crate::utils::include::IncludeResolver;
+use crate::utils::interrupt;
+use crate::utils::jobs::JobManager;
+use crate::utils::keys::KeyConfig;
+use crate::utils::lint::{self, Linter};
+use crate::utils::pager::ResultPager;
+use crate::utils::prompt::Prompt;
+use crate::utils::repl::{self, resolve_script, Repl, SplitMode, Unresolved};
+use crate::utils::trace;
+use crate::utils::run_loop;
+use crate::utils::shrust::EditHelper;
+use crate::utils::theme::Theme;
+use crate::utils::vars::{parse_var, Variables};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Cli {
     #[clap(short, long, parse(from_os_str), value_name = "FILE")]
     conf: Option<PathBuf>,
+
+    /// log every request to the engine, to ~/.byzer/trace.jsonl by default
+    #[clap(long, value_name = "FILE")]
+    trace: Option<Option<PathBuf>>,
+
+    /// a local variable for `${key}` in the scripts, may be repeated
+    #[clap(long = "var", value_name = "KEY=VALUE", parse(try_from_str = parse_var))]
+    vars: Vec<(String, String)>,
+
+    #[clap(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// send a request recorded by `--trace` again
+    Replay {
+        #[clap(parse(from_os_str), value_name = "TRACE")]
+        trace: PathBuf,
+
+        /// line of the request in the trace file, the last one by default
+        #[clap(long)]
+        line: Option<usize>,
+
+        /// send to this url instead of the recorded one
+        #[clap(long)]
+        url: Option<String>,
+    },
+
+    /// check scripts for mistakes without executing them, exits with 1 on errors.
+    /// Local includes and `--var` variables are resolved first, as when the
+    /// scripts are executed
+    Lint {
+        #[clap(parse(from_os_str), value_name = "FILE", required = true)]
+        files: Vec<PathBuf>,
+    },
+
+    /// rewrite scripts in the canonical layout
+    Fmt {
+        /// only list the files which are not formatted, exits with 1 if any
+        #[clap(long)]
+        check: bool,
+
+        #[clap(parse(from_os_str), value_name = "FILE", required = true)]
+        files: Vec<PathBuf>,
+    },
 }
 
 fn main() {
     let cli = Cli::parse();
 
+    if let Some(Commands::Fmt { check, files }) = &cli.command {
+        if !format_files(files, *check) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let mut config_path = ".mlsql.config";
     if let Some(_config_path) = cli.conf.as_deref() {
         config_path = _config_path.to_str().unwrap();
@@ -49,39 +119,145 @@ fn main() {
     let mut byzer_conf = ByzerConf::new(byzer_home.to_string(), config_path_opt);
     byzer_conf.build();
 
+    if let Some(Commands::Replay { trace, line, url }) = &cli.command {
+        if let Err(e) = trace::replay(trace, *line, url.as_deref(), &byzer_conf) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Commands::Lint { files }) = &cli.command {
+        let includes = IncludeResolver::new(&byzer_conf);
+        let vars = Variables::new(&byzer_conf, &cli.vars);
+        if lint_files(&Linter::new(&byzer_conf), &includes, &vars, files) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let java_exec = byzer_conf.build_java_command();
     // println!("{}",java_exec);
 
     let mut exec_c = std::process::Command::new(java_exec);
     exec_c.args(byzer_conf.byzer_command.as_slice());
-    let mut pid = exec_c.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn();
-
-    print_pretty_header(&byzer_conf);
-
-    run_loop(move |s| {
-        println!("");
-        let mut pb = ExecutingProgressBar::new();
-        let monitor_handler = pb.start_monitor("Executing:".to_string());
-        
-        let (status,res) = run_script(
-            byzer_conf.engine_url.as_str(),
-            s,
-            byzer_conf.owner.as_str(),
-            &byzer_conf.request_config,
-        );
-
-        if status != 200 {
-            pb.send_finish_signal(false);
-        } else {
-            pb.send_finish_signal(true);
+    // in a process group of its own, so that Ctrl-C in the terminal only
+    // reaches the shell, which decides whether to cancel a query
+    #[cfg(unix)]
+    exec_c.process_group(0);
+    let pid = exec_c.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn();
+
+    let engine = Arc::new(EngineClient::new(&byzer_conf));
+    if let Some(trace_path) = &cli.trace {
+        match engine.tracer().enable(trace_path.as_deref()) {
+            Ok(path) => println!("Tracing requests to {}\n", path.display()),
+            Err(e) => println!("Fail to open the trace file: {}\n", e),
         }
+    }
+
+    interrupt::install_handler();
+
+    if !print_pretty_header(&engine) {
+        if let Ok(mut child) = pid {
+            let _ = child.kill();
+        }
+        std::process::exit(1);
+    }
+
+    let jobs = JobManager::new(Arc::clone(&engine), &byzer_conf);
+    let session = Arc::new(Mutex::new(SessionState::default()));
+    let pager = ResultPager::new(Arc::clone(&engine), &byzer_conf, Arc::clone(&session));
+    let commands = repl::commands();
 
-        monitor_handler.join().unwrap();
+    let mut edit_helper = EditHelper::new();
+    edit_helper
+        .set_engine_completer(EngineCompleter::new(Arc::clone(&engine), &byzer_conf))
+        .set_local_completer(LocalCompleter::new(session))
+        .set_command_completer(CommandCompleter::new(commands.specs()))
+        .set_theme(Theme::new(&byzer_conf));
 
-        utils::print_as_table(res.as_str());
+    let history = History::new(&byzer_conf);
+    let scripts = history.scripts();
+    let editor = ExternalEditor::new(&byzer_conf);
+    let prompt = Prompt::new(&byzer_conf);
+    let mut repl = Repl::new(engine, pager, jobs, history, editor.clone(), prompt.state());
+    repl.split = byzer_conf.shell_config_or("execute.split", SplitMode::Off);
+    repl.linter = Linter::new(&byzer_conf);
+    repl.vars = Variables::new(&byzer_conf, &cli.vars);
+    repl.includes = IncludeResolver::new(&byzer_conf);
+
+    let key_config = KeyConfig::new(&byzer_conf);
+    run_loop(edit_helper, &scripts, &prompt, &key_config, &editor, move |s| {
+        println!();
+        repl.handle(&commands, s)
     });
 
-    if pid.is_ok() {
-        pid.unwrap().kill();
+    if let Ok(mut child) = pid {
+        let _ = child.kill();
     };
 }
+
+/// print the lints of `files` once their includes and variables are resolved
+/// as for execution, returns whether there is an error among them.
+fn lint_files(linter: &Linter, includes: &IncludeResolver, vars: &Variables, files: &[PathBuf]) -> bool {
+    let mut has_error = false;
+    for file in files {
+        let origin = file.display().to_string();
+        let script = match std::fs::read_to_string(file) {
+            Ok(script) => script,
+            Err(e) => {
+                eprintln!("Fail to read {}: {}", origin, e);
+                has_error = true;
+                continue;
+            }
+        };
+        match resolve_script(includes, vars, script.as_str(), Some(origin.as_str())) {
+            Ok((script, _)) => {
+                let lints = linter.lint(script.as_str(), &[]);
+                has_error |= lint::print_lints(&lints, script.as_str(), Some(origin.as_str()));
+            }
+            Err(Unresolved::Includes(e)) => {
+                println!("{}: {}", origin, e);
+                has_error = true;
+            }
+            Err(Unresolved::Variables(e)) => {
+                print!("{}", e);
+                has_error = true;
+            }
+        }
+    }
+    has_error
+}
+
+/// format `files` in place, or with `check` list those which are not
+/// formatted. Returns whether all of them were formatted.
+fn format_files(files: &[PathBuf], check: bool) -> bool {
+    let mut ok = true;
+    for file in files {
+        let origin = file.display();
+        let result = std::fs::read_to_string(file)
+            .map_err(|e| format!("Fail to read {}: {}", origin, e))
+            .and_then(|script| {
+                let formatted = formatter::format(script.as_str()).map_err(|e| format!("{}: {}", origin, e))?;
+                Ok((script, formatted))
+            });
+        match result {
+            Ok((script, formatted)) if script == formatted => {}
+            Ok(_) if check => {
+                println!("{}", origin);
+                ok = false;
+            }
+            Ok((_, formatted)) => {
+                if let Err(e) = std::fs::write(file, formatted) {
+                    eprintln!("Fail to write {}: {}", origin, e);
+                    ok = false;
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                ok = false;
+            }
+        }
+    }
+    ok
+}
@@ -1,29 +1,67 @@
-use reqwest;
+use colored::*;
+use lazy_static::lazy_static;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
-use std::option::Option;
 use serde_json::{json, Value};
 use std::collections::HashMap;
-use std::process::Output;
-use std::thread::sleep;
+use std::option::Option;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep};
 use std::time;
 use stringreader::StringReader;
 
+pub mod callback;
+pub mod commands;
+pub mod completion;
 pub mod conf;
+pub mod diagnostic;
+pub mod engine;
+pub mod external_editor;
+#[cfg(test)]
+mod fixtures;
+pub mod formatter;
+pub mod history;
+pub mod include;
+pub mod interrupt;
+pub mod jobs;
+pub mod keys;
+pub mod lexer;
+pub mod lint;
+pub mod pager;
+pub mod parser;
+pub mod prompt;
+pub mod repl;
 mod printer;
 mod reader;
 pub mod shrust;
 mod table_printer;
+pub mod theme;
+pub mod trace;
+pub mod vars;
 pub mod progress_bar;
 
-use crate::utils::conf::ByzerConf;
+use crate::utils::commands::Flow;
+use crate::utils::engine::EngineClient;
+use crate::utils::external_editor::ExternalEditor;
+use crate::utils::interrupt::InterruptListener;
+use crate::utils::keys::KeyConfig;
 use crate::utils::printer::{
-    JsonTable, PlainTextTableFormat, PlainTextTablePrinter, Printer,
+    HtmlTableFormat, HtmlTablePrinter, JsonTable, PlainTextTableFormat, PlainTextTablePrinter, Printer, TableFormat,
 };
 use crate::utils::reader::{OneShotValueReader, ValueReader};
+use crate::utils::prompt::Prompt;
 use crate::utils::shrust::EditHelper;
 use crate::utils::progress_bar::ExecutingProgressBar;
 
+/// two Ctrl-C within this interval exit the shell.
+const DOUBLE_INTERRUPT_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+lazy_static! {
+    /// how results are printed, changed by `:format`.
+    static ref OUTPUT_FORMAT: Mutex<TableFormat> =
+        Mutex::new(TableFormat::PlainText(PlainTextTableFormat::Default));
+}
 
 pub fn array_to_map<'a>(array: &'a [&str]) -> HashMap<&'a str, &'a str> {
     let mut element_map = HashMap::new();
@@ -42,76 +80,193 @@ pub fn map_to_array<'a>(map: HashMap<&'a str, &'a str>) -> Vec<&'a str> {
     elements
 }
 
-pub fn run_script(
-    endpoint: &str,
+/// execute `sql` as a new job while showing a spinner, with `extra_params`
+/// sent along with the script. Ctrl-C cancels the job on the engine and `None`
+/// is returned instead of its result.
+pub fn execute_script(
+    engine: &Arc<EngineClient>,
     sql: &str,
-    owner: &str,
-    config: &HashMap<String, String>,
-) -> (u16, String) {
-    let client = reqwest::blocking::Client::new();
-    let mut params = HashMap::new();
-    params.insert("sql", sql);
-    params.insert("owner", owner);
-    params.insert("outputSize", "50");
-
-    for (k, v) in config {
-        params.insert((*k).as_str(), (*v).as_str());
+    extra_params: Vec<(String, String)>,
+) -> Option<(u16, String)> {
+    let listener = InterruptListener::new();
+    let job_name = engine.new_job_name();
+    let sql = sql.to_string();
+    run_cancellable(
+        engine,
+        job_name.as_str(),
+        &listener,
+        move |engine, job_name| {
+            let extra_params = extra_params
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect::<Vec<_>>();
+            engine.run_job(sql.as_str(), job_name, extra_params.as_slice())
+        },
+        |(status, _)| *status == 200,
+    )
+}
+
+/// run `work` for the job `job_name` in a worker thread while showing a spinner,
+/// `succeeded` tells how the spinner ends. When `listener` gets Ctrl-C the job
+/// is killed on the engine and `None` is returned.
+pub fn run_cancellable<T, F, S>(
+    engine: &Arc<EngineClient>,
+    job_name: &str,
+    listener: &InterruptListener,
+    work: F,
+    succeeded: S,
+) -> Option<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&EngineClient, &str) -> T + Send + 'static,
+    S: Fn(&T) -> bool,
+{
+    let mut pb = ExecutingProgressBar::new();
+    let monitor_handler = pb.start_monitor("Executing:".to_string());
+
+    let (tx, rx) = channel();
+    {
+        let engine = Arc::clone(engine);
+        let job_name = job_name.to_string();
+        thread::spawn(move || {
+            let _ = tx.send(work(&engine, job_name.as_str()));
+        });
     }
 
-    let resp = client.post(endpoint).form(&params).send();
-    match resp {
-        Ok(item) => {
-            let status = item.status().as_u16();
-            let s = item.text().unwrap();
-            if status == 200 {
-                (200, s)
-            } else {
-                (status, s)
+    let result = loop {
+        match rx.recv_timeout(time::Duration::from_millis(100)) {
+            Ok(res) => {
+                pb.send_finish_signal(succeeded(&res));
+                break Some(res);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if listener.interrupted() {
+                    // the worker thread returns once the engine drops the job,
+                    // its result is discarded with the channel.
+                    engine.kill_job(job_name);
+                    pb.send_cancel_signal();
+                    break None;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                pb.send_finish_signal(false);
+                break None;
             }
         }
-        Err(e) => (500, e.to_string()),
-    }
+    };
+
+    monitor_handler.join().unwrap();
+    result
 }
 
 pub fn print_as_table(data: &str) {
-    let mut str_reader = StringReader::new(data);
-    let mut onshot_reader = OneShotValueReader::new(str_reader);
-    let newdata = match onshot_reader.read_value(Some(100)) {
+    let str_reader = StringReader::new(data);
+    let onshot_reader = OneShotValueReader::new(str_reader);
+    let newdata = match onshot_reader.read_value(None) {
         Ok(v) => v,
-        Err(e) => {
-            let temp_v = json!({ "message": data });
-            temp_v
+        Err(_) => json!({ "message": data }),
+    };
+    print_value(&newdata);
+}
+
+pub fn print_value(value: &Value) {
+    let table = JsonTable::new(None, value);
+    match *OUTPUT_FORMAT.lock().unwrap() {
+        TableFormat::PlainText(format) => PlainTextTablePrinter::new(vec![], format).print(&table),
+        TableFormat::Html(format) => HtmlTablePrinter::new(format).print(&table),
+    }
+    .unwrap();
+}
+
+/// print results as `default` or `markdown` tables, or as `html` or
+/// `html-raw` tables.
+pub fn set_output_format(format: &str) -> Result<(), String> {
+    *OUTPUT_FORMAT.lock().unwrap() = format.parse()?;
+    Ok(())
+}
+
+/// switch to the output format following the current one, returns its name.
+pub fn toggle_output_format() -> &'static str {
+    let mut format = OUTPUT_FORMAT.lock().unwrap();
+    let (next, name) = match *format {
+        TableFormat::PlainText(PlainTextTableFormat::Default) => {
+            (TableFormat::PlainText(PlainTextTableFormat::Markdown), "markdown")
         }
+        TableFormat::PlainText(PlainTextTableFormat::Markdown) => (TableFormat::Html(HtmlTableFormat::Styled), "html"),
+        TableFormat::Html(HtmlTableFormat::Styled) => (TableFormat::Html(HtmlTableFormat::Raw), "html-raw"),
+        TableFormat::Html(HtmlTableFormat::Raw) => (TableFormat::PlainText(PlainTextTableFormat::Default), "default"),
     };
-    let table = JsonTable::new(None, &newdata);
-    PlainTextTablePrinter::new(vec![], PlainTextTableFormat::Default)
-        .print(&table)
-        .unwrap();
+    *format = next;
+    name
 }
 
-pub fn run_loop<F>(func: F)
-    where
-        F: Fn(&str),
+/// read statements until `func` asks to quit, `history` is what the up arrow
+/// shows before anything is entered. Ctrl-C discards what is typed, Ctrl-D and
+/// two Ctrl-C in a row are handed to `func` as `:quit`. A statement is edited
+/// as a whole across its lines, Enter inserts a newline until it is complete.
+/// The editor is set up by `key_config`, its `open-editor` keys open the
+/// statement in `editor`.
+pub fn run_loop<F>(
+    edit_helper: EditHelper,
+    history: &[String],
+    prompt: &Prompt,
+    key_config: &KeyConfig,
+    editor: &ExternalEditor,
+    mut func: F,
+) where
+    F: FnMut(&str) -> Flow,
 {
-    let mut rl = Editor::new();
-    let edit_helper = EditHelper::new();
+    let line_buffer = edit_helper.line_buffer();
+    let mut rl = Editor::with_config(key_config.config());
     rl.set_helper(Some(edit_helper));
-    
-    let prompt = ">> ";
+    for entry in history {
+        rl.add_history_entry(entry.as_str());
+    }
+    key_config.bind(&mut rl, editor, &line_buffer);
+
+    // the text of the next prompt
+    let mut initial = String::new();
+    let mut last_interrupt: Option<time::Instant> = None;
     loop {
-        let readline = rl.readline(prompt);
+        let readline = rl.readline_with_initial(prompt.render().as_str(), (initial.as_str(), ""));
+        initial = String::new();
         match readline {
             Ok(line) => {
-                rl.add_history_entry(line.as_str());
-                func(&line)
+                // a statement submitted by a key binding replaces the input, the
+                // rest of it comes back at the next prompt
+                let statement = match line_buffer.submitted.lock().unwrap().take() {
+                    Some((script, rest)) => {
+                        println!("{}", script);
+                        initial = rest;
+                        script
+                    }
+                    None => line,
+                };
+                if statement.trim().is_empty() {
+                    continue;
+                }
+                rl.add_history_entry(statement.as_str());
+                match func(&statement) {
+                    Flow::Continue => {}
+                    Flow::Quit => break,
+                    Flow::Edit(script) => initial = script,
+                }
             },
             Err(ReadlineError::Interrupted) => {
-                println!("CTRL-C");
-                break;
+                if last_interrupt.is_some_and(|at| at.elapsed() < DOUBLE_INTERRUPT_INTERVAL) {
+                    last_interrupt = None;
+                    if func(":quit") == Flow::Quit {
+                        break;
+                    }
+                } else {
+                    last_interrupt = Some(time::Instant::now());
+                    println!("{}", "(Ctrl-C again, Ctrl-D or `:quit` to exit)".dimmed());
+                }
             }
             Err(ReadlineError::Eof) => {
-                println!("CTRL-D");
-                break;
+                if func(":quit") == Flow::Quit {
+                    break;
+                }
             }
             Err(err) => {
                 println!("Error: {:?}", err);
@@ -121,14 +276,9 @@ pub fn run_loop<F>(func: F)
     }
 }
 
-pub fn show_version(byzer_conf: &ByzerConf) -> Option<String> {
+pub fn show_version(engine: &EngineClient) -> Option<String> {
     let version_info_query = "!show version;";
-    let (status, res) = run_script(
-        byzer_conf.engine_url.as_str(),
-        version_info_query,
-        byzer_conf.owner.as_str(),
-        &byzer_conf.request_config,
-    );
+    let (status, res) = engine.probe(version_info_query);
     if status != 200 {
         None
     } else {
@@ -137,20 +287,29 @@ pub fn show_version(byzer_conf: &ByzerConf) -> Option<String> {
 }
 
 
-pub fn print_pretty_header(byzer_conf: &ByzerConf) {
+/// wait for the engine to start and print its version. Returns false when it
+/// doesn't start in time or Ctrl-C is pressed meanwhile.
+pub fn print_pretty_header(engine: &EngineClient) -> bool {
     //println!("Byzer-lang interpreter is starting...\n");
     let mut count = 0;
     let max_count = 30;
     let mut pb = ExecutingProgressBar::new();
     let monitor_handler = pb.start_monitor("Byzer-lang interpreter is starting...".to_string());
-    while show_version(byzer_conf).is_none() && count < max_count {
+    while show_version(engine).is_none() && count < max_count {
+        if interrupt::take_interrupted() {
+            pb.send_finish_signal(false);
+            let _ = monitor_handler.join();
+            return false;
+        }
         sleep(time::Duration::from_secs(1));
         count += 1
     }
 
     if count == max_count {
         pb.send_finish_signal(false);
-        panic!("Fail to start byzer-lang interpreter")
+        let _ = monitor_handler.join();
+        println!("Fail to start byzer-lang interpreter");
+        return false;
     }
 
     pb.send_finish_signal(true);
@@ -158,7 +317,7 @@ pub fn print_pretty_header(byzer_conf: &ByzerConf) {
 
     print_logo();
 
-    let res = show_version(byzer_conf).unwrap();
+    let res = show_version(engine).unwrap();
     let version: Value = serde_json::from_str(&res).unwrap();
     println!("\n\nversion: {:?}", version[0]["version"].as_str().unwrap());
     println!("buildBy: {:?}", version[0]["buildBy"].as_str().unwrap());
@@ -171,7 +330,8 @@ pub fn print_pretty_header(byzer_conf: &ByzerConf) {
     println!("branch: {:?}", version[0]["branch"].as_str().unwrap());
     println!("url: {:?}", version[0]["url"].as_str().unwrap());
     println!("core: {:?}", version[0]["core"].as_str().unwrap());
-    println!("\nType \"CTRL-C\" or \"CTRL-D\" to exit the program.\n");
+    println!("\nType \":help\" to list the commands, \":quit\" or \"CTRL-D\" to exit the program.\n");
+    true
 }
 
 pub fn print_logo() {
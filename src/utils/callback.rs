@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// how long a client may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A minimal http server receiving what the engine posts to the `callback`
+/// url of the scripts run with `async=true`. The url holds a random token of
+/// the session, requests without it are refused.
+pub struct CallbackServer {
+    url: String,
+    token: String,
+}
+
+impl CallbackServer {
+    /// listen on `bind`, `handle` is called with the form parameters of every
+    /// request, those of the query string included. `url` is where the engine
+    /// reaches the server, the bound address by default. Bodies larger than
+    /// `max_body` bytes are refused.
+    pub fn start<F>(bind: &str, url: Option<String>, max_body: usize, handle: F) -> io::Result<Self>
+    where
+        F: Fn(HashMap<String, String>) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind(bind)?;
+        let url = match url {
+            Some(url) => url,
+            None => format!("http://{}/", listener.local_addr()?),
+        };
+        let token = new_token()?;
+        let handle = Arc::new(handle);
+        {
+            let token = token.clone();
+            // a slow client only holds up its own thread
+            thread::spawn(move || {
+                for mut stream in listener.incoming().flatten() {
+                    let (token, handle) = (token.clone(), Arc::clone(&handle));
+                    thread::spawn(move || {
+                        let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+                        match read_form(&stream, max_body) {
+                            Ok(mut params) => {
+                                if params.remove("token").as_deref() == Some(token.as_str()) {
+                                    respond(&mut stream, "200 OK");
+                                    handle(params);
+                                } else {
+                                    respond(&mut stream, "403 Forbidden");
+                                }
+                            }
+                            Err(status) => respond(&mut stream, status),
+                        }
+                    });
+                }
+            });
+        }
+        Ok(Self { url, token })
+    }
+
+    /// the callback url of the job `job_name`.
+    pub fn url(&self, job_name: &str) -> String {
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("token", &self.token)
+            .append_pair("jobName", job_name)
+            .finish();
+        let separator = match self.url.find('?') {
+            None => "?",
+            Some(_) if self.url.ends_with(['?', '&']) => "",
+            Some(_) => "&",
+        };
+        format!("{}{}{}", self.url, separator, query)
+    }
+}
+
+/// 128 random bits in hex.
+fn new_token() -> io::Result<String> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// the form parameters of the request on `stream`, or the status to answer.
+fn read_form(stream: &TcpStream, max_body: usize) -> Result<HashMap<String, String>, &'static str> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).map_err(|_| "400 Bad Request")?;
+    let target = request_line.split_whitespace().nth(1).ok_or("400 Bad Request")?.to_string();
+
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).map_err(|_| "400 Bad Request")? == 0 {
+            return Err("400 Bad Request");
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = Some(value.trim().parse::<usize>().map_err(|_| "400 Bad Request")?);
+            } else if name.trim().eq_ignore_ascii_case("transfer-encoding") {
+                return Err("411 Length Required");
+            }
+        }
+    }
+    let length = length.ok_or("411 Length Required")?;
+    if length > max_body {
+        return Err("413 Payload Too Large");
+    }
+    let mut body = vec![];
+    reader
+        .take(length as u64)
+        .read_to_end(&mut body)
+        .map_err(|_| "400 Bad Request")?;
+    if body.len() < length {
+        return Err("400 Bad Request");
+    }
+
+    let query = target.split_once('?').map(|(_, query)| query).unwrap_or_default();
+    Ok(form_urlencoded::parse(query.as_bytes())
+        .chain(form_urlencoded::parse(&body))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect())
+}
+
+fn respond(stream: &mut TcpStream, status: &str) {
+    let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn receive_callbacks() {
+        let (tx, rx) = channel();
+        let server = CallbackServer::start("127.0.0.1:0", None, 1024, move |params| tx.send(params).unwrap()).unwrap();
+        let client = reqwest::blocking::Client::new();
+        let post = |url: &str, res: &str| client.post(url).form(&[("stat", "succeeded"), ("res", res)]).send().unwrap();
+
+        let resp = post(&server.url("byzer-shell-1-1"), "[{\"a\":\"x&y=1\"}]");
+        assert_eq!(resp.status().as_u16(), 200);
+        let params = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(params["jobName"], "byzer-shell-1-1");
+        assert_eq!(params["stat"], "succeeded");
+        assert_eq!(params["res"], "[{\"a\":\"x&y=1\"}]");
+        assert!(!params.contains_key("token"));
+
+        let without_token = format!("{}?jobName=byzer-shell-1-2", server.url);
+        assert_eq!(post(&without_token, "[]").status().as_u16(), 403);
+        let wrong_token = server.url("byzer-shell-1-2").replace(&server.token, "0");
+        assert_eq!(post(&wrong_token, "[]").status().as_u16(), 403);
+        assert_eq!(post(&server.url("byzer-shell-1-2"), &"x".repeat(2048)).status().as_u16(), 413);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn stall_no_other_callbacks() {
+        let (tx, rx) = channel();
+        let server = CallbackServer::start("127.0.0.1:0", None, 1024, move |params| tx.send(params).unwrap()).unwrap();
+        let address = server.url.trim_start_matches("http://").trim_end_matches('/').to_string();
+        let _idle = TcpStream::connect(address).unwrap();
+
+        let resp = reqwest::blocking::Client::new()
+            .post(server.url("byzer-shell-1-1"))
+            .form(&[("stat", "succeeded")])
+            .send()
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+        assert!(rx.recv_timeout(Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn append_to_the_query_of_the_url() {
+        let server = |url: &str| CallbackServer {
+            url: url.to_string(),
+            token: String::from("t"),
+        };
+        assert_eq!(server("http://h:7000/").url("j"), "http://h:7000/?token=t&jobName=j");
+        assert_eq!(server("http://h/cb?user=a").url("j"), "http://h/cb?user=a&token=t&jobName=j");
+        assert_eq!(server("http://h/cb?").url("j"), "http://h/cb?token=t&jobName=j");
+    }
+}
@@ -0,0 +1,284 @@
+//! Meta commands, the lines starting with `:` which the shell handles itself
+//! instead of sending them to the engine.
+
+use colored::*;
+
+use crate::utils::completion::complete_path;
+
+/// what the read loop does after a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+    /// start the next line with this text
+    Edit(String),
+}
+
+/// runs a command with its parsed arguments, fails with a message telling
+/// what is wrong with them.
+pub type Handler<S> = fn(&mut S, &[&str]) -> Result<Flow, String>;
+
+/// One argument of a command, as written in its usage: `<file>` is required,
+/// `[pattern]` is optional and `on|off` only takes one of the listed words.
+struct Param<'a> {
+    name: &'a str,
+    optional: bool,
+    choices: Vec<&'a str>,
+}
+
+/// The name, arguments and help of a command, which is all the completer
+/// needs to know about it.
+#[derive(Debug, Clone)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    /// such as `<file>`, `on|off` or `[pattern]`, the last argument takes the
+    /// rest of the line
+    pub args: &'static str,
+    pub help: &'static str,
+}
+
+impl CommandSpec {
+    fn params(&self) -> Vec<Param<'static>> {
+        self.args
+            .split_whitespace()
+            .map(|arg| {
+                let optional = arg.starts_with('[');
+                let name = arg.trim_matches(|c| c == '[' || c == ']' || c == '<' || c == '>');
+                let choices = if name.contains('|') { name.split('|').collect() } else { vec![] };
+                Param {
+                    name,
+                    optional,
+                    choices,
+                }
+            })
+            .collect()
+    }
+
+    pub fn usage(&self) -> String {
+        if self.args.is_empty() {
+            format!(":{}", self.name)
+        } else {
+            format!(":{} {}", self.name, self.args)
+        }
+    }
+
+    /// split what follows the command name into its arguments.
+    pub fn parse_args<'l>(&self, line: &'l str) -> Result<Vec<&'l str>, String> {
+        let params = self.params();
+        let mut args = vec![];
+        let mut rest = line.trim();
+        for (i, param) in params.iter().enumerate() {
+            if rest.is_empty() {
+                if !param.optional {
+                    return Err(format!("missing <{}>", param.name));
+                }
+                break;
+            }
+            let arg = if i + 1 == params.len() {
+                rest
+            } else {
+                rest.split_whitespace().next().unwrap_or_default()
+            };
+            if !param.choices.is_empty() && !param.choices.contains(&arg) {
+                return Err(format!("expected {}, got `{}`", param.choices.join(" or "), arg));
+            }
+            args.push(arg);
+            rest = rest[arg.len()..].trim_start();
+        }
+        if !rest.is_empty() {
+            return Err(format!("unexpected `{}`", rest));
+        }
+        Ok(args)
+    }
+}
+
+/// All the meta commands of the shell. Features plug their commands in with
+/// `register`, `:help` is always there.
+pub struct CommandRegistry<S> {
+    commands: Vec<(CommandSpec, Option<Handler<S>>)>,
+}
+
+impl<S> CommandRegistry<S> {
+    pub fn new() -> Self {
+        let help = CommandSpec {
+            name: "help",
+            args: "[command]",
+            help: "list the commands, or show how to use one",
+        };
+        Self {
+            commands: vec![(help, None)],
+        }
+    }
+
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        args: &'static str,
+        help: &'static str,
+        handler: Handler<S>,
+    ) -> &mut Self {
+        self.commands.retain(|(spec, _)| spec.name != name);
+        self.commands.push((CommandSpec { name, args, help }, Some(handler)));
+        self
+    }
+
+    pub fn specs(&self) -> Vec<CommandSpec> {
+        self.commands.iter().map(|(spec, _)| spec.clone()).collect()
+    }
+
+    /// run `command`, a line without its leading `:`.
+    pub fn run(&self, state: &mut S, command: &str) -> Flow {
+        let command = command.trim();
+        let (name, rest) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        let (spec, handler) = match self.commands.iter().find(|(spec, _)| spec.name == name) {
+            Some(command) => command,
+            None => {
+                println!("Unknown command: :{}, `:help` to list the commands.", name);
+                return Flow::Continue;
+            }
+        };
+
+        let result = spec.parse_args(rest).and_then(|args| match handler {
+            Some(handler) => handler(state, &args),
+            None => {
+                self.print_help(args.first().copied());
+                Ok(Flow::Continue)
+            }
+        });
+        result.unwrap_or_else(|e| {
+            println!("{}\nUsage: {}", e, spec.usage());
+            Flow::Continue
+        })
+    }
+
+    fn print_help(&self, topic: Option<&str>) {
+        let topic = topic.map(|t| t.trim_start_matches(':'));
+        match topic {
+            Some(topic) => match self.commands.iter().find(|(spec, _)| spec.name == topic) {
+                Some((spec, _)) => println!("{}\n    {}", spec.usage(), spec.help),
+                None => println!("Unknown command: :{}", topic),
+            },
+            None => {
+                let width = self.commands.iter().map(|(spec, _)| spec.usage().len()).max().unwrap_or(0);
+                for (spec, _) in &self.commands {
+                    let usage = format!("{:width$}", spec.usage(), width = width);
+                    println!("{}  {}", usage.bold(), spec.help);
+                }
+            }
+        }
+    }
+}
+
+/// Completes command names after `:`, and their arguments: choices, command
+/// names for `:help` and paths for `<file>` arguments.
+pub struct CommandCompleter {
+    specs: Vec<CommandSpec>,
+}
+
+impl CommandCompleter {
+    pub fn new(specs: Vec<CommandSpec>) -> Self {
+        Self { specs }
+    }
+
+    /// `None` if `line` is not a command.
+    pub fn complete(&self, line: &str, pos: usize) -> Option<(usize, Vec<String>)> {
+        let before = line[..pos].trim_start();
+        let offset = pos - before.len();
+        let before = before.strip_prefix(':')?;
+
+        let (name, rest) = match before.split_once(char::is_whitespace) {
+            Some(split) => split,
+            None => {
+                let names = self
+                    .specs
+                    .iter()
+                    .filter(|spec| spec.name.starts_with(before))
+                    .map(|spec| spec.name.to_string())
+                    .collect();
+                return Some((offset + 1, names));
+            }
+        };
+
+        let spec = self.specs.iter().find(|spec| spec.name == name)?;
+        let params = spec.params();
+        let words = rest.split_whitespace().count();
+        let (index, word) = if rest.ends_with(char::is_whitespace) || rest.is_empty() {
+            (words, "")
+        } else {
+            (words - 1, rest.split_whitespace().last().unwrap_or_default())
+        };
+        let start = pos - word.len();
+        let param = match params.get(index) {
+            Some(param) => param,
+            None => return Some((start, vec![])),
+        };
+
+        let candidates = if !param.choices.is_empty() {
+            param.choices.iter().filter(|c| c.starts_with(word)).map(|c| c.to_string()).collect()
+        } else if param.name == "command" {
+            self.specs
+                .iter()
+                .filter(|spec| spec.name.starts_with(word))
+                .map(|spec| spec.name.to_string())
+                .collect()
+        } else if param.name == "file" {
+            return Some(complete_path(word, start));
+        } else {
+            vec![]
+        };
+        Some((start, candidates))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(args: &'static str) -> CommandSpec {
+        CommandSpec {
+            name: "test",
+            args,
+            help: "",
+        }
+    }
+
+    #[test]
+    fn parse_required_and_optional_args() {
+        assert_eq!(spec("<file>").parse_args(" a b.byzer "), Ok(vec!["a b.byzer"]));
+        assert_eq!(spec("<file>").parse_args(""), Err("missing <file>".to_string()));
+        assert_eq!(spec("[pattern]").parse_args(""), Ok(vec![]));
+        assert_eq!(spec("[on|off] [file]").parse_args("on /tmp/t"), Ok(vec!["on", "/tmp/t"]));
+        assert_eq!(spec("").parse_args("x"), Err("unexpected `x`".to_string()));
+    }
+
+    #[test]
+    fn parse_choices() {
+        assert_eq!(spec("on|off").parse_args("off"), Ok(vec!["off"]));
+        assert_eq!(
+            spec("on|off").parse_args("yes"),
+            Err("expected on or off, got `yes`".to_string())
+        );
+    }
+
+    #[test]
+    fn complete_names_and_choices() {
+        let completer = CommandCompleter::new(vec![
+            CommandSpec {
+                name: "timing",
+                args: "on|off",
+                help: "",
+            },
+            CommandSpec {
+                name: "trace",
+                args: "[on|off] [file]",
+                help: "",
+            },
+        ]);
+        assert_eq!(
+            completer.complete(":t", 2),
+            Some((1, vec!["timing".to_string(), "trace".to_string()]))
+        );
+        assert_eq!(completer.complete(":timing o", 9), Some((8, vec!["on".to_string(), "off".to_string()])));
+        assert_eq!(completer.complete("select", 6), None);
+    }
+}
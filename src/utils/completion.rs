@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::utils::conf::{expand_home, ByzerConf};
+use crate::utils::engine::EngineClient;
+use crate::utils::lexer;
+
+lazy_static! {
+    /// a backtick path right after `load <format>.` or `as <format>.`
+    static ref PATH_CONTEXT: Regex = Regex::new(r"(?i)\b(?:load|as)\s+\w+\.`([^`]*)$").unwrap();
+
+    /// `<table>.<column>`
+    static ref QUALIFIED_COLUMN: Regex = Regex::new(r"([A-Za-z_]\w*)\.\w*$").unwrap();
+}
+
+static BANG_COMMANDS: &[&str] = &[
+    "!cache", "!callback", "!delta", "!desc", "!else", "!emptyTable", "!fi", "!fs", "!hdfs",
+    "!hive", "!if", "!kafka", "!kill", "!lastCommand", "!lastTableName", "!plugin", "!println",
+    "!profiler", "!python", "!ray", "!runScript", "!saveFile", "!show", "!tableRepartition",
+    "!then", "!uncache",
+];
+
+/// the last successful answer of the engine.
+struct LastSuggestion {
+    script: String,
+    pos: usize,
+    at: Instant,
+    candidates: Vec<String>,
+}
+
+/// Asks the engine for completion candidates, table names, columns, ET names
+/// and data source formats, by running the script in `autoSuggest` mode.
+pub struct EngineCompleter {
+    engine: Arc<EngineClient>,
+    timeout: Duration,
+    min_interval: Duration,
+    last: Mutex<Option<LastSuggestion>>,
+}
+
+impl EngineCompleter {
+    /// ```text
+    /// shell.completion.timeout=800ms
+    /// shell.completion.min.interval=300ms
+    /// ```
+    pub fn new(engine: Arc<EngineClient>, byzer_conf: &ByzerConf) -> Self {
+        Self {
+            engine,
+            timeout: byzer_conf.shell_duration_or("completion.timeout", Duration::from_millis(800)),
+            min_interval: byzer_conf.shell_duration_or("completion.min.interval", Duration::from_millis(300)),
+            last: Mutex::new(None),
+        }
+    }
+
+    /// candidates for the word before `pos` in `script`, `None` if the engine
+    /// failed or did not answer in time. Requests are rate limited: within
+    /// `min_interval` of the last answer it is reused for the same input, and
+    /// no request is sent for others. Failures are not remembered.
+    pub fn suggest(&self, script: &str, pos: usize) -> Option<Vec<String>> {
+        let mut last = self.last.lock().unwrap();
+        if let Some(last) = last.as_ref() {
+            if last.at.elapsed() < self.min_interval {
+                return if last.script == script && last.pos == pos {
+                    Some(last.candidates.clone())
+                } else {
+                    None
+                };
+            }
+        }
+
+        let before = &script[..pos];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or_default().chars().count();
+        let (status, body) = self.engine.auto_suggest(script, line, column, self.timeout);
+        let candidates = if status == 200 {
+            parse_suggestions(body.as_str())
+        } else {
+            None
+        };
+
+        *last = candidates.as_ref().map(|candidates| LastSuggestion {
+            script: script.to_string(),
+            pos,
+            at: Instant::now(),
+            candidates: candidates.clone(),
+        });
+        candidates
+    }
+}
+
+/// the engine answers with `[{"name": "...", "metaTable": {...}, "extra": {...}}]`.
+fn parse_suggestions(body: &str) -> Option<Vec<String>> {
+    let suggestions = serde_json::from_str::<Value>(body).ok()?;
+    let mut names = vec![];
+    for suggestion in suggestions.as_array()? {
+        if let Some(name) = suggestion["name"].as_str() {
+            if !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    Some(names)
+}
+
+/// What the shell learnt from the statements it executed: the temp tables they
+/// defined and the columns of their results.
+#[derive(Default)]
+pub struct SessionState {
+    /// most recently defined last
+    tables: Vec<String>,
+    columns: HashMap<String, Vec<String>>,
+}
+
+impl SessionState {
+    pub fn tables(&self) -> &[String] {
+        &self.tables
+    }
+
+    /// record the tables defined by a successfully executed `script`, `first_row`
+    /// gives the columns of the table its last statement defined, if any.
+    pub fn record(&mut self, script: &str, first_row: Option<&Value>) {
+        let statements = lexer::split_statements(script);
+        for table in statements.iter().copied().filter_map(lexer::output_table) {
+            self.tables.retain(|t| t != table);
+            self.tables.push(table.to_string());
+        }
+
+        let last_table = statements.last().copied().and_then(lexer::output_table);
+        if let (Some(table), Some(Value::Object(row))) = (last_table, first_row) {
+            self.columns.insert(table.to_string(), row.keys().cloned().collect());
+        }
+    }
+}
+
+/// Completes from the session state and the local file system, without asking
+/// the engine.
+pub struct LocalCompleter {
+    session: Arc<Mutex<SessionState>>,
+}
+
+impl LocalCompleter {
+    pub fn new(session: Arc<Mutex<SessionState>>) -> Self {
+        Self { session }
+    }
+
+    /// candidates in contexts which only this completer knows about, paths
+    /// inside backticks and `!` commands, with the position they start at.
+    pub fn complete_context(&self, line: &str, pos: usize) -> Option<(usize, Vec<String>)> {
+        let before = &line[..pos];
+        if let Some(captures) = PATH_CONTEXT.captures(before) {
+            let path = captures.get(1)?;
+            return Some(complete_path(path.as_str(), path.start()));
+        }
+
+        let word_start = before
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '!'))
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let word = &before[word_start..];
+        if word.starts_with('!') {
+            let candidates = BANG_COMMANDS
+                .iter()
+                .filter(|c| c.starts_with(word))
+                .map(|c| c.to_string())
+                .collect();
+            return Some((word_start, candidates));
+        }
+        None
+    }
+
+    /// table names and columns starting with `word`, only the columns of the
+    /// table when the word is qualified as `<table>.<word>`.
+    pub fn complete_names(&self, line: &str, pos: usize, word: &str) -> Vec<String> {
+        let session = self.session.lock().unwrap();
+        let word = word.to_lowercase();
+        let matches = |name: &&String| name.to_lowercase().starts_with(&word);
+
+        if let Some(captures) = QUALIFIED_COLUMN.captures(&line[..pos]) {
+            if let Some(columns) = session.columns.get(&captures[1]) {
+                return columns.iter().filter(matches).cloned().collect();
+            }
+        }
+
+        let mut names = session.tables.iter().rev().filter(matches).cloned().collect::<Vec<_>>();
+        for columns in session.columns.values() {
+            for column in columns.iter().filter(matches) {
+                if !names.contains(column) {
+                    names.push(column.clone());
+                }
+            }
+        }
+        names
+    }
+}
+
+/// entries of the directory of `prefix` whose names start with its file part,
+/// directories end with `/`. `offset` is where `prefix` starts in the line, a
+/// leading `~` stands for the home directory.
+pub fn complete_path(prefix: &str, offset: usize) -> (usize, Vec<String>) {
+    let (dir, file_prefix) = match prefix.rfind('/') {
+        Some(i) => (&prefix[..=i], &prefix[i + 1..]),
+        None => ("", prefix),
+    };
+    let start = offset + dir.len();
+    let read_dir = match fs::read_dir(expand_home(if dir.is_empty() { "." } else { dir })) {
+        Ok(read_dir) => read_dir,
+        Err(_) => return (start, vec![]),
+    };
+
+    let mut candidates = read_dir
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with(file_prefix) || (name.starts_with('.') && !file_prefix.starts_with('.')) {
+                return None;
+            }
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => Some(format!("{}/", name)),
+                _ => Some(name),
+            }
+        })
+        .collect::<Vec<_>>();
+    candidates.sort();
+    (start, candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fixtures::TempDir;
+
+    #[test]
+    fn record_defined_tables() {
+        let mut session = SessionState::default();
+        let script = "-- select 1 as commented;\nselect \"x as quoted;\" as a;\n/* as b; */ load csv.`/tmp/c` as c;";
+        session.record(script, Some(&serde_json::json!({"x": 1})));
+        assert_eq!(session.tables(), ["a", "c"]);
+        assert_eq!(session.columns["c"], ["x"]);
+
+        // the row belongs to the last statement, which defines no table
+        session.record("select 2 as d;\nsave overwrite d as csv.`/tmp/d`;", Some(&serde_json::json!({"y": 1})));
+        assert_eq!(session.tables(), ["a", "c", "d"]);
+        assert!(!session.columns.contains_key("d"));
+    }
+
+    #[test]
+    fn complete_paths() {
+        let dir = TempDir::new("completion");
+        fs::create_dir(dir.path().join("ab")).unwrap();
+        for name in ["a.csv", ".a", "b.csv"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        let prefix = format!("{}/", dir.path().display());
+        let (start, candidates) = complete_path(&format!("{}a", prefix), 5);
+        assert_eq!(start, 5 + prefix.len());
+        assert_eq!(candidates, ["a.csv", "ab/"]);
+        assert_eq!(complete_path(&format!("{}.", prefix), 0).1, [".a"]);
+        assert!(complete_path(&format!("{}x/", prefix), 0).1.is_empty());
+    }
+}
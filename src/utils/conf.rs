@@ -1,9 +1,13 @@
 use std::collections::HashMap;
 use std::env;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::{BufRead, BufReader};
 use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
 use crate::utils::{array_to_map, map_to_array};
 
@@ -13,6 +17,8 @@ pub struct ByzerConf {
     config_path: Option<String>,
     pub engine_url: String,
     pub request_config: HashMap<String, String>,
+    /// settings of byzer-shell itself, read from `shell.*` keys with the prefix stripped
+    pub shell_config: HashMap<String, String>,
     pub byzer_command: Vec<String>,
     pub owner: String,
 }
@@ -24,6 +30,54 @@ fn scan_port(port: u16) -> bool {
     }
 }
 
+/// parse durations like `500ms`, `10s`, `5m` or `1h`, a bare number means seconds.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split_at = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split_at);
+    let number = number.parse::<u64>().ok()?;
+    match unit.trim() {
+        "ms" => Some(Duration::from_millis(number)),
+        "" | "s" => Some(Duration::from_secs(number)),
+        "m" => Some(Duration::from_secs(number * 60)),
+        "h" => Some(Duration::from_secs(number * 3600)),
+        _ => None,
+    }
+}
+
+fn home_dir() -> PathBuf {
+    let home = env::var("HOME")
+        .or_else(|_| env::var("USERPROFILE"))
+        .unwrap_or_else(|_| String::from("."));
+    PathBuf::from(home)
+}
+
+/// `~/.byzer`, where byzer-shell keeps its own files.
+pub fn byzer_dir() -> PathBuf {
+    home_dir().join(".byzer")
+}
+
+/// `path` with a leading `~` replaced by the home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix('~') {
+        Some("") => home_dir(),
+        Some(rest) if rest.starts_with('/') || rest.starts_with(std::path::MAIN_SEPARATOR) => {
+            home_dir().join(&rest[1..])
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+/// open `path` with `options`, creating it so that only the user can read it.
+pub fn open_private(options: &mut OpenOptions, path: &Path) -> std::io::Result<File> {
+    options.create(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(path)
+}
+
 fn available_port() -> u16 {
     let mut start_port = 9003;
     while scan_port(start_port) {
@@ -61,6 +115,7 @@ impl ByzerConf {
             config_path,
             engine_url: String::from("http://127.0.0.1:9003"),
             request_config: HashMap::new(),
+            shell_config: HashMap::new(),
             byzer_command: vec![],
             owner: String::from("admin"),
         };
@@ -141,6 +196,7 @@ impl ByzerConf {
         ];
 
         let mut request_config = HashMap::new();
+        let mut shell_config = HashMap::new();
         let default_config = array_to_map(&default_config_array);
         let mut modified_default_config = HashMap::new();
 
@@ -181,6 +237,10 @@ impl ByzerConf {
             if k.starts_with("user.") {
                 request_config.insert(k.trim_start_matches("user.").to_string(), v.to_string());
             }
+
+            if k.starts_with("shell.") {
+                shell_config.insert(k.trim_start_matches("shell.").to_string(), v.to_string());
+            }
         }
 
         let default_interpreter_address = format!("http://127.0.0.1:{}", interpreter_port);
@@ -195,6 +255,8 @@ impl ByzerConf {
 
         self.request_config = request_config;
 
+        self.shell_config = shell_config;
+
         let mut temp_temp_config = HashMap::new();
 
         for (k, v) in &modified_default_config {
@@ -235,6 +297,23 @@ impl ByzerConf {
         self
     }
 
+    /// get a `shell.*` setting parsed as `T`, falling back to `default` when it
+    /// is missing or malformed.
+    pub fn shell_config_or<T: FromStr>(&self, key: &str, default: T) -> T {
+        self.shell_config
+            .get(key)
+            .and_then(|v| v.parse::<T>().ok())
+            .unwrap_or(default)
+    }
+
+    /// get a `shell.*` duration setting such as `500ms`, `10s` or `5m`.
+    pub fn shell_duration_or(&self, key: &str, default: Duration) -> Duration {
+        self.shell_config
+            .get(key)
+            .and_then(|v| parse_duration(v))
+            .unwrap_or(default)
+    }
+
     fn read_config_from_file(&mut self) -> HashMap<String, String> {
         let mut config = HashMap::new();
         if self.config_path.is_none() {
@@ -244,20 +323,33 @@ impl ByzerConf {
 
         let b_reader = BufReader::new(File::open(Path::new(p)).unwrap());
 
+        // keys under a `[section]` line are read as `section.key`, up to the next
+        // section: there is no way back to the top level
+        let mut section = String::new();
         let lines = b_reader.lines();
-        for _line in lines {
-            if let Ok(line) = _line {
-                let line1 = line.trim();
-                if line1.starts_with("#") {
-                    continue;
-                }
-                if line1 == "" {
-                    continue;
-                }
+        for line in lines.map_while(Result::ok) {
+            let line1 = line.trim();
+            if line1.starts_with('#') {
+                continue;
+            }
+            if line1.is_empty() {
+                continue;
+            }
+            if line1.starts_with('[') && line1.ends_with(']') {
+                section = line1[1..line1.len() - 1].trim().to_owned();
+                continue;
+            }
 
-                let kv = line1.splitn(2, "=").into_iter().collect::<Vec<_>>();
-                config.insert(kv[0].trim().to_owned(), kv[1].trim().to_owned());
+            let kv = line1.splitn(2, '=').collect::<Vec<_>>();
+            if kv.len() < 2 {
+                continue;
             }
+            let key = if section.is_empty() {
+                kv[0].trim().to_owned()
+            } else {
+                format!("{}.{}", section, kv[0].trim())
+            };
+            config.insert(key, kv[1].trim().to_owned());
         }
         config
     }
@@ -0,0 +1,193 @@
+use colored::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::sync::Mutex;
+
+use crate::utils::include::SourceMap;
+
+lazy_static! {
+    /// the raw text of the last error, shown by `:trace`.
+    static ref LAST_TRACE: Mutex<Option<String>> = Mutex::new(None);
+
+    static ref EXCEPTION_LINE: Regex =
+        Regex::new(r"^(?:Caused by:\s*)?((?:[\w$]+\.)+[\w$]*(?:Exception|Error))(?::\s*(.*))?$").unwrap();
+
+    /// positions reported by the Byzer parser, `[row:2 column:7]`, or by antlr, `line 2:7`.
+    static ref SCRIPT_POSITION: Regex =
+        Regex::new(r"\[row:\s*(\d+)\s+column:\s*(\d+)\s*\]|\bline (\d+):(\d+)").unwrap();
+}
+
+/// An error returned by the engine, parsed out of the text blob which holds
+/// the message and the java stack trace.
+#[derive(Debug)]
+pub struct EngineError {
+    /// class of the root cause, such as `org.apache.spark.sql.AnalysisException`
+    pub exception: Option<String>,
+    /// message of the root cause
+    pub message: String,
+    /// one based line and zero based column in the submitted script
+    pub position: Option<(usize, usize)>,
+}
+
+impl EngineError {
+    pub fn parse(body: &str) -> EngineError {
+        let lines = body
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !is_stack_frame(line))
+            .collect::<Vec<_>>();
+
+        // the last `Caused by:` is the root cause, otherwise the first exception
+        let exception_line = lines
+            .iter()
+            .rev()
+            .find(|line| line.starts_with("Caused by:") && EXCEPTION_LINE.is_match(line))
+            .or_else(|| lines.iter().find(|line| EXCEPTION_LINE.is_match(line)));
+
+        let (exception, message) = match exception_line.and_then(|line| EXCEPTION_LINE.captures(line)) {
+            Some(captures) => (
+                captures.get(1).map(|m| m.as_str().to_string()),
+                captures
+                    .get(2)
+                    .map(|m| m.as_str().trim().to_string())
+                    .unwrap_or_default(),
+            ),
+            None => (None, lines.first().map(|line| line.to_string()).unwrap_or_default()),
+        };
+
+        // only the messages, the names in the stack frames may look like positions too
+        let position = lines.iter().find_map(|line| SCRIPT_POSITION.captures(line)).and_then(|captures| {
+            let line = captures.get(1).or_else(|| captures.get(3))?;
+            let column = captures.get(2).or_else(|| captures.get(4))?;
+            Some((line.as_str().parse().ok()?, column.as_str().parse().ok()?))
+        });
+
+        EngineError {
+            exception,
+            message,
+            position,
+        }
+    }
+
+    /// render the error, with a caret under the offending line of `script` if
+    /// the position is known. With `source_map`, the line is reported in the
+    /// file it was included from.
+    pub fn render(&self, script: &str, source_map: Option<&SourceMap>) -> String {
+        let mut out = String::new();
+        match &self.exception {
+            Some(exception) => out.push_str(&format!("{}\n", exception.red().bold())),
+            None => out.push_str(&format!("{}\n", "Error".red().bold())),
+        }
+        if !self.message.is_empty() {
+            out.push_str(&format!("{}\n", self.message));
+        }
+
+        if let Some((line_num, column)) = self.position {
+            if let Some(line) = script.lines().nth(line_num.saturating_sub(1)) {
+                let (origin, origin_line) = match source_map {
+                    Some(source_map) => source_map.locate(line_num),
+                    None => (None, line_num),
+                };
+                let gutter = origin_line.to_string();
+                let padding = " ".repeat(gutter.len());
+                let location = match origin {
+                    Some(origin) => format!("{}:{}, column {}", origin.display(), origin_line, column),
+                    None => format!("line {}, column {}", origin_line, column),
+                };
+                out.push_str(&format!("{}{} {}\n", padding, "-->".blue(), location));
+                out.push_str(&format!("{} {} {}\n", gutter.blue(), "|".blue(), line));
+                // keep tabs so the caret lines up with the echoed line
+                let caret_offset = line
+                    .chars()
+                    .take(column)
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect::<String>();
+                out.push_str(&format!(
+                    "{} {} {}{}\n",
+                    padding,
+                    "|".blue(),
+                    caret_offset,
+                    "^".red().bold()
+                ));
+            }
+        }
+        out
+    }
+}
+
+/// `\tat xxx(Xxx.scala:12)` and `... 42 more`
+fn is_stack_frame(line: &str) -> bool {
+    line.starts_with("at ") || (line.starts_with("...") && line.ends_with("more"))
+}
+
+/// print the error returned for `script`, the stack trace is kept for `:trace`.
+/// `source_map` tells where the lines of `script` come from, if it has inlined
+/// includes.
+pub fn print_error(script: &str, body: &str, source_map: Option<&SourceMap>) {
+    let error = EngineError::parse(body);
+    print!("{}", error.render(script, source_map));
+    if body.lines().any(|line| is_stack_frame(line.trim())) {
+        println!("{}", "(`:trace` to show the stack trace)".dimmed());
+    }
+    *LAST_TRACE.lock().unwrap() = Some(body.to_string());
+}
+
+/// print the full text of the last error, for `:trace`.
+pub fn print_last_trace() {
+    match LAST_TRACE.lock().unwrap().as_ref() {
+        Some(trace) => println!("{}", trace),
+        None => println!("No error so far."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_syntax_error() {
+        let body = "streaming.dsl.parser.MLSQLSyntaxErrorException: MLSQL Parser error in [row:2 column:7] \
+mismatched input 'form' expecting {<EOF>, 'as'}
+\tat streaming.dsl.parser.MLSQLErrorStrategy.recover(MLSQLErrorStrategy.scala:24)
+\tat streaming.dsl.ScriptSQLExec$.parse(ScriptSQLExec.scala:176)
+\tat tech.mlsql.job.JobManager$.run(JobManager.scala:74)";
+        let error = EngineError::parse(body);
+        assert_eq!(error.exception.as_deref(), Some("streaming.dsl.parser.MLSQLSyntaxErrorException"));
+        assert_eq!(
+            error.message,
+            "MLSQL Parser error in [row:2 column:7] mismatched input 'form' expecting {<EOF>, 'as'}"
+        );
+        assert_eq!(error.position, Some((2, 7)));
+
+        colored::control::set_override(false);
+        let rendered = error.render("select 1 as a;\nload csv form t;", None);
+        assert!(rendered.ends_with(" --> line 2, column 7\n2 | load csv form t;\n  |        ^\n"));
+    }
+
+    #[test]
+    fn parse_root_cause() {
+        let body = "java.lang.RuntimeException: fail to execute the script
+\tat tech.mlsql.job.JobManager$.run(JobManager.scala:74)
+Caused by: java.lang.RuntimeException: wrapped
+\tat streaming.rest.RestController.script(RestController.scala:141)
+Caused by: org.apache.spark.sql.AnalysisException: Table or view not found: t; line 1 pos 14;
+'Project [*]
++- 'UnresolvedRelation [t]
+
+\tat org.apache.spark.sql.catalyst.analysis.package$AnalysisErrorAt.failAnalysis(package.scala:42)
+\tat org.antlr.v4.runtime.Parser.parse(line 3:4)
+\t... 42 more";
+        let error = EngineError::parse(body);
+        assert_eq!(error.exception.as_deref(), Some("org.apache.spark.sql.AnalysisException"));
+        assert_eq!(error.message, "Table or view not found: t; line 1 pos 14;");
+        assert_eq!(error.position, None);
+    }
+
+    #[test]
+    fn parse_plain_message() {
+        let error = EngineError::parse("\nline 3:12 no viable alternative at input 'select'\n");
+        assert_eq!(error.exception, None);
+        assert_eq!(error.message, "line 3:12 no viable alternative at input 'select'");
+        assert_eq!(error.position, Some((3, 12)));
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use reqwest::blocking::{Client, Response};
+
+use crate::utils::conf::{byzer_dir, expand_home, ByzerConf};
+use crate::utils::trace::{HttpTracer, TraceRecord};
+
+/// the longest wait between two retries, however many attempts failed.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// how long the engine has to answer a probe, such as `!show version;`.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// an http client with the timeouts of `shell.http.*`. A request gives up
+/// after `shell.http.request.timeout`, one hour by default so that a hung
+/// engine doesn't block the shell forever, `0` for no limit.
+pub fn http_client(byzer_conf: &ByzerConf) -> Client {
+    let connect_timeout = byzer_conf.shell_duration_or("http.connect.timeout", Duration::from_secs(5));
+    let request_timeout = Some(byzer_conf.shell_duration_or("http.request.timeout", Duration::from_secs(3600)))
+        .filter(|timeout| !timeout.is_zero());
+
+    Client::builder()
+        .connect_timeout(connect_timeout)
+        .timeout(request_timeout)
+        .build()
+        .expect("Fail to build the http client")
+}
+
+/// how a failed request is retried. Only connection errors are retried, and
+/// script execution is retried only when `retry_scripts` is set, since a
+/// script may have side effects on the engine even if the connection broke.
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub backoff: Duration,
+    pub retry_scripts: bool,
+}
+
+impl RetryPolicy {
+    /// a single attempt.
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            backoff: Duration::ZERO,
+            retry_scripts: false,
+        }
+    }
+
+    /// wait time before the given (zero based) retry, doubled on every attempt
+    /// up to `MAX_BACKOFF`.
+    fn backoff_for(&self, attempt: u32) -> Duration {
+        self.backoff
+            .checked_mul(2u32.saturating_pow(attempt))
+            .map_or(MAX_BACKOFF, |backoff| backoff.min(MAX_BACKOFF))
+    }
+}
+
+/// A long-lived client of the engine's `/run/script` endpoint, all the requests
+/// of the shell share its connection pool.
+pub struct EngineClient {
+    client: Client,
+    endpoint: String,
+    owner: String,
+    request_config: HashMap<String, String>,
+    retry: RetryPolicy,
+    output_size: String,
+    job_counter: AtomicUsize,
+    tracer: HttpTracer,
+}
+
+impl EngineClient {
+    /// build the client from `shell.http.*` settings:
+    ///
+    /// ```text
+    /// shell.http.connect.timeout=5s
+    /// shell.http.request.timeout=1h
+    /// shell.http.retry.max=3
+    /// shell.http.retry.backoff=500ms
+    /// shell.http.retry.scripts=false
+    /// shell.trace.file=~/.byzer/trace.jsonl
+    /// ```
+    pub fn new(byzer_conf: &ByzerConf) -> Self {
+        let client = http_client(byzer_conf);
+
+        let retry = RetryPolicy {
+            max_retries: byzer_conf.shell_config_or("http.retry.max", 3),
+            backoff: byzer_conf.shell_duration_or("http.retry.backoff", Duration::from_millis(500)),
+            retry_scripts: byzer_conf.shell_config_or("http.retry.scripts", false),
+        };
+
+        Self {
+            client,
+            endpoint: byzer_conf.engine_url.clone(),
+            owner: byzer_conf.owner.clone(),
+            request_config: byzer_conf.request_config.clone(),
+            retry,
+            output_size: byzer_conf.shell_config_or("output.size", 50usize).to_string(),
+            job_counter: AtomicUsize::new(0),
+            tracer: HttpTracer::new(
+                byzer_conf
+                    .shell_config
+                    .get("trace.file")
+                    .map(|path| expand_home(path))
+                    .unwrap_or_else(|| byzer_dir().join("trace.jsonl")),
+            ),
+        }
+    }
+
+    pub fn tracer(&self) -> &HttpTracer {
+        &self.tracer
+    }
+
+    /// a job name unique among the shells attached to the engine, scripts sent
+    /// with it as `jobName` can be cancelled by `kill_job`.
+    pub fn new_job_name(&self) -> String {
+        let n = self.job_counter.fetch_add(1, Ordering::SeqCst) + 1;
+        format!("byzer-shell-{}-{}", process::id(), n)
+    }
+
+    /// execute a script as the job `job_name`, it is not retried unless
+    /// `shell.http.retry.scripts` is enabled.
+    pub fn run_job(&self, sql: &str, job_name: &str, extra_params: &[(&str, &str)]) -> (u16, String) {
+        let mut params = vec![("jobName", job_name)];
+        params.extend_from_slice(extra_params);
+        self.send(sql, params.as_slice(), false)
+    }
+
+    /// like `run_job`, but returns once the response headers arrive so that the
+    /// body can be read while the engine sends it. It is never retried, and
+    /// the body is not traced.
+    pub fn stream_job(&self, sql: &str, job_name: &str, extra_params: &[(&str, &str)]) -> Result<Response, String> {
+        let mut params = vec![("jobName", job_name)];
+        params.extend_from_slice(extra_params);
+        let params = self.form_params(sql, params.as_slice());
+
+        let started = Instant::now();
+        let resp = self.client.post(self.endpoint.as_str()).form(&params).send();
+        let record = TraceRecord::new(self.endpoint.as_str(), &params, started.elapsed());
+        match resp {
+            Ok(resp) => {
+                self.tracer
+                    .record(record.with_response(resp.status().as_u16(), resp.headers(), None));
+                Ok(resp)
+            }
+            Err(e) => {
+                self.tracer.record(record.with_error(&e.to_string()));
+                Err(e.to_string())
+            }
+        }
+    }
+
+    /// ask the engine to cancel the job, it is safe to retry.
+    pub fn kill_job(&self, job_name: &str) -> (u16, String) {
+        self.run_query(format!("!kill {};", job_name).as_str())
+    }
+
+    /// execute a read-only script such as `!show tables;`, it is safe to retry.
+    pub fn run_query(&self, sql: &str) -> (u16, String) {
+        self.send(sql, &[], true)
+    }
+
+    /// execute a read-only script once, within `PROBE_TIMEOUT`, to tell whether
+    /// the engine is up. The caller polls, so it is never retried.
+    pub fn probe(&self, sql: &str) -> (u16, String) {
+        self.send_with(sql, &[], &RetryPolicy::none(), Some(PROBE_TIMEOUT))
+    }
+
+    /// send `sql` with `extra_params` merged over the default form parameters,
+    /// returns the http status and the response body, or status 500 with the
+    /// error message if the engine can not be reached.
+    pub fn send(&self, sql: &str, extra_params: &[(&str, &str)], idempotent: bool) -> (u16, String) {
+        if idempotent || self.retry.retry_scripts {
+            self.send_with(sql, extra_params, &self.retry, None)
+        } else {
+            self.send_with(sql, extra_params, &RetryPolicy::none(), None)
+        }
+    }
+
+    /// ask the engine for completion candidates at the one based `line` and
+    /// zero based `column` of `sql`, giving up after `timeout`.
+    pub fn auto_suggest(&self, sql: &str, line: usize, column: usize, timeout: Duration) -> (u16, String) {
+        let (line, column) = (line.to_string(), column.to_string());
+        let extra_params = [
+            ("executeMode", "autoSuggest"),
+            ("lineNum", line.as_str()),
+            ("columnNum", column.as_str()),
+            ("isDebug", "false"),
+        ];
+        // retrying would only make the user wait longer for completion
+        self.send_with(sql, &extra_params, &RetryPolicy::none(), Some(timeout))
+    }
+
+    /// same as `send`, retried as `retry` says, `timeout` overrides
+    /// `shell.http.request.timeout`.
+    fn send_with(
+        &self,
+        sql: &str,
+        extra_params: &[(&str, &str)],
+        retry: &RetryPolicy,
+        timeout: Option<Duration>,
+    ) -> (u16, String) {
+        let params = self.form_params(sql, extra_params);
+
+        let mut attempt = 0;
+        loop {
+            let started = Instant::now();
+            let mut request = self.client.post(self.endpoint.as_str()).form(&params);
+            if let Some(timeout) = timeout {
+                request = request.timeout(timeout);
+            }
+            match request.send() {
+                Ok(resp) => {
+                    let status = resp.status().as_u16();
+                    let headers = resp.headers().clone();
+                    let body = resp.text();
+                    let record = TraceRecord::new(self.endpoint.as_str(), &params, started.elapsed());
+                    return match body {
+                        Ok(body) => {
+                            self.tracer
+                                .record(record.with_response(status, &headers, Some(body.as_str())));
+                            (status, body)
+                        }
+                        Err(e) => {
+                            self.tracer.record(
+                                record.with_response(status, &headers, None).with_error(&e.to_string()),
+                            );
+                            (500, e.to_string())
+                        }
+                    };
+                }
+                Err(e) => {
+                    let record = TraceRecord::new(self.endpoint.as_str(), &params, started.elapsed());
+                    self.tracer.record(record.with_error(&e.to_string()));
+                    if e.is_connect() && attempt < retry.max_retries {
+                        sleep(retry.backoff_for(attempt));
+                        attempt += 1;
+                    } else {
+                        return (500, e.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    fn form_params<'a>(&'a self, sql: &'a str, extra_params: &[(&'a str, &'a str)]) -> HashMap<&'a str, &'a str> {
+        let mut params = HashMap::new();
+        params.insert("sql", sql);
+        params.insert("owner", self.owner.as_str());
+        params.insert("outputSize", self.output_size.as_str());
+
+        for (k, v) in &self.request_config {
+            params.insert(k.as_str(), v.as_str());
+        }
+        for (k, v) in extra_params {
+            params.insert(*k, *v);
+        }
+        params
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cap_backoff() {
+        let retry = RetryPolicy {
+            max_retries: 64,
+            backoff: Duration::from_millis(500),
+            retry_scripts: false,
+        };
+        let waits = (0..5).map(|attempt| retry.backoff_for(attempt)).collect::<Vec<_>>();
+        assert_eq!(waits, [500, 1000, 2000, 4000, 8000].map(Duration::from_millis));
+        assert_eq!(retry.backoff_for(6), MAX_BACKOFF);
+        assert_eq!(retry.backoff_for(63), MAX_BACKOFF);
+    }
+}
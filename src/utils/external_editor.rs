@@ -0,0 +1,127 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rustyline::{Cmd, ConditionalEventHandler, Event, EventContext, Movement, RepeatCount};
+
+use crate::utils::conf::ByzerConf;
+use crate::utils::shrust::LineBuffer;
+
+static EDITS: AtomicUsize = AtomicUsize::new(0);
+
+/// Opens a statement in `$VISUAL` or `$EDITOR` (`vi` when neither is set)
+/// through a temp `.byzer` file. With `shell.edit.submit=true` the edited
+/// statement is executed as soon as the editor closes, otherwise it is loaded
+/// back at the prompt for review.
+#[derive(Clone)]
+pub struct ExternalEditor {
+    pub submit: bool,
+}
+
+impl ExternalEditor {
+    pub fn new(byzer_conf: &ByzerConf) -> Self {
+        Self {
+            submit: byzer_conf.shell_config_or("edit.submit", false),
+        }
+    }
+
+    /// wait for the editor to close and return the edited text.
+    pub fn edit(&self, text: &str) -> Result<String, String> {
+        let command = env::var("VISUAL")
+            .or_else(|_| env::var("EDITOR"))
+            .ok()
+            .filter(|c| !c.trim().is_empty())
+            .unwrap_or_else(|| String::from("vi"));
+        // the editor may come with arguments, such as `code --wait`
+        let mut words = command.split_whitespace();
+        let program = words.next().unwrap_or("vi");
+
+        let path = write_temp_file(text)?;
+        let status = Command::new(program).args(words).arg(&path).status();
+        let edited = fs::read_to_string(&path);
+        let _ = fs::remove_file(&path);
+
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => return Err(format!("{} exited with {}", program, status)),
+            Err(e) => return Err(format!("Fail to run {}: {}", program, e)),
+        }
+        edited
+            .map(|s| s.trim_end().to_string())
+            .map_err(|e| format!("Fail to read {}: {}", path.display(), e))
+    }
+
+    /// opens the statement being typed, with its previous lines, when its key
+    /// is pressed. An edited statement to submit is left in `line_buffer`,
+    /// since the line can't be both replaced and accepted by a single command.
+    pub fn key_handler(&self, line_buffer: Arc<LineBuffer>) -> EditKeyHandler {
+        EditKeyHandler {
+            editor: self.clone(),
+            line_buffer,
+        }
+    }
+}
+
+/// write `text` to a new file of the temp directory which only the user can
+/// read, a file which already exists is never reused.
+fn write_temp_file(text: &str) -> Result<PathBuf, String> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    for _ in 0..16 {
+        let path = env::temp_dir().join(format!(
+            "byzer-shell-{}-{}-{}.byzer",
+            std::process::id(),
+            nanos,
+            EDITS.fetch_add(1, Ordering::Relaxed)
+        ));
+        match options.open(&path) {
+            Ok(mut file) => {
+                return file
+                    .write_all(text.as_bytes())
+                    .map(|_| path.clone())
+                    .map_err(|e| format!("Fail to write {}: {}", path.display(), e))
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("Fail to create {}: {}", path.display(), e)),
+        }
+    }
+    Err(String::from("Fail to create a temp file"))
+}
+
+pub struct EditKeyHandler {
+    editor: ExternalEditor,
+    line_buffer: Arc<LineBuffer>,
+}
+
+impl ConditionalEventHandler for EditKeyHandler {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        match self.editor.edit(ctx.line()) {
+            Ok(script) if self.editor.submit => {
+                *self.line_buffer.submitted.lock().unwrap() = Some((script, String::new()));
+                Some(Cmd::AcceptLine)
+            }
+            Ok(script) => Some(Cmd::Replace(Movement::WholeBuffer, Some(script))),
+            // the terminal is in raw mode, the line is drawn again as it was
+            // below the error
+            Err(e) => {
+                print!("\r\n{}\r\n", e);
+                let _ = io::stdout().flush();
+                Some(Cmd::Replace(Movement::WholeBuffer, Some(ctx.line().to_string())))
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! Golden file tests shared by the parser and the formatter, and the
+//! temporary directories of tests which write files.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// a directory of the temp directory named after the test and the process,
+/// removed with its content when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("byzer-shell-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// every `tests/fixtures/<dir>/*.byzer` goes through `render` and is compared
+/// with the `.<extension>` file next to it, which `verify` checks further.
+/// `UPDATE_FIXTURES=1` writes the expected files instead.
+pub fn check<R, V>(dir: &str, extension: &str, render: R, verify: V)
+where
+    R: Fn(&str) -> String,
+    V: Fn(&str) -> Result<(), String>,
+{
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(dir);
+    let update = std::env::var("UPDATE_FIXTURES").is_ok();
+    let mut paths = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "byzer"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    let mut failed = vec![];
+    for path in paths {
+        let script = fs::read_to_string(&path).unwrap();
+        let actual = render(&script);
+        let expected_path = path.with_extension(extension);
+        if update {
+            fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+        if actual != expected {
+            failed.push(format!("{}:\n{}", path.display(), actual));
+        } else if let Err(e) = verify(&actual) {
+            failed.push(format!("{}: {}", path.display(), e));
+        }
+    }
+    assert!(failed.is_empty(), "fixtures differ:\n{}", failed.join("\n"));
+}
@@ -0,0 +1,516 @@
+//! Prints Byzer scripts in a canonical layout: lowercase keywords, one
+//! statement per line unless it is too long, `where` options one per line
+//! under each other and the clauses of a `select` indented. Comments are kept
+//! and `'''` blocks are left as they are.
+
+use std::ops::Range;
+
+use crate::utils::lexer::{self, Token, TokenKind};
+use crate::utils::lint::line_and_column;
+use crate::utils::parser::{self, OptionPair, Statement, StatementKind};
+
+/// longer statements are split over several lines.
+const MAX_WIDTH: usize = 80;
+
+/// the statements whose options are laid out by the formatter.
+const BYZER_KEYWORDS: [&str; 9] = ["load", "save", "connect", "set", "train", "run", "predict", "register", "include"];
+
+/// the keywords of statements other than the Byzer ones, lowercased too.
+const SQL_KEYWORDS: [&str; 58] = [
+    "select", "from", "where", "group", "by", "order", "having", "limit", "join", "left", "right", "inner", "outer",
+    "full", "cross", "natural", "anti", "semi", "on", "using", "and", "or", "not", "as", "in", "is", "null", "union",
+    "all", "distinct", "case", "when", "then", "else", "end", "like", "between", "asc", "desc", "with", "over",
+    "partition", "exists", "insert", "into", "overwrite", "table", "create", "drop", "view", "if", "values",
+    "refresh", "truncate", "explain", "temporary", "lateral", "window",
+];
+
+/// a `select` clause starting with one of these goes on a line of its own.
+const CLAUSE_KEYWORDS: [&str; 11] = [
+    "from", "where", "group", "order", "having", "limit", "union", "intersect", "except", "lateral", "window",
+];
+
+const JOIN_KEYWORDS: [&str; 8] = ["join", "left", "right", "inner", "full", "cross", "natural", "outer"];
+
+/// A statement written on its own, or a line of an `!if` block, and how many
+/// blocks it is nested in.
+struct Piece<'a> {
+    /// with the `;`
+    span: Range<usize>,
+    level: usize,
+    /// the level of the comments after it, one more after the line of a branch
+    level_after: usize,
+    /// `None` for the `!if`, `!elif`, `!else` and `!fi` lines
+    kind: Option<&'a StatementKind>,
+}
+
+/// the pieces of `statements` in the order of the script.
+fn pieces<'a>(statements: &'a [Statement], level: usize, pieces_: &mut Vec<Piece<'a>>) {
+    for statement in statements {
+        match &statement.kind {
+            StatementKind::If { branches, fi } => {
+                for branch in branches {
+                    pieces_.push(Piece {
+                        span: branch.span.clone(),
+                        level,
+                        level_after: level + 1,
+                        kind: None,
+                    });
+                    pieces(&branch.body, level + 1, pieces_);
+                }
+                if let Some(fi) = fi {
+                    pieces_.push(Piece {
+                        span: fi.start..statement.span.end,
+                        level,
+                        level_after: level,
+                        kind: None,
+                    });
+                }
+            }
+            kind => pieces_.push(Piece {
+                span: statement.span.clone(),
+                level,
+                level_after: level,
+                kind: Some(kind),
+            }),
+        }
+    }
+}
+
+/// format `script`, which is refused when it can't be parsed.
+pub fn format(script: &str) -> Result<String, String> {
+    let parsed = parser::parse(script);
+    if let Some(error) = parsed.errors.first() {
+        let (line, column) = line_and_column(script, error.span.start);
+        return Err(format!("line {}, column {}: {}", line, column, error.message));
+    }
+    let mut statements = vec![];
+    pieces(&parsed.statements, 0, &mut statements);
+    let mut statements = statements.into_iter();
+
+    let tokens = lexer::tokenize(script);
+    let mut out = String::new();
+    let mut level = 0;
+    let mut blank_line = false;
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = tokens[i];
+        match token.kind {
+            TokenKind::Whitespace => {
+                // blank lines between statements are kept, but only one
+                blank_line = !out.is_empty() && token.text.matches('\n').count() > 1;
+                i += 1;
+            }
+            TokenKind::LineComment | TokenKind::BlockComment => {
+                if blank_line {
+                    out.push('\n');
+                }
+                out.push_str(&"  ".repeat(level));
+                out.push_str(token.text.trim_end());
+                out.push('\n');
+                blank_line = false;
+                i += 1;
+            }
+            TokenKind::Semicolon => i += 1,
+            _ => {
+                let piece = statements.next().expect("every statement is parsed");
+                let end = i + tokens[i..]
+                    .iter()
+                    .position(|t| t.start >= piece.span.end)
+                    .unwrap_or(tokens.len() - i);
+                let statement = match tokens[end - 1].kind {
+                    TokenKind::Semicolon => &tokens[i..end - 1],
+                    _ => &tokens[i..end],
+                };
+
+                if blank_line {
+                    out.push('\n');
+                }
+                let indent = "  ".repeat(piece.level);
+                out.push_str(&indent);
+                let mut writer = Writer::new(indent);
+                write_statement(&mut writer, statement, piece.kind);
+                writer.push(";");
+                out.push_str(&writer.out);
+                blank_line = false;
+                level = piece.level_after;
+                i = end;
+
+                // a comment on the same line as the `;` stays there
+                let comment = match (tokens.get(i), tokens.get(i + 1)) {
+                    (Some(t), _) if t.is_trivia() && t.kind != TokenKind::Whitespace => Some(i),
+                    (Some(ws), Some(t))
+                        if ws.kind == TokenKind::Whitespace
+                            && !ws.text.contains('\n')
+                            && matches!(t.kind, TokenKind::LineComment | TokenKind::BlockComment) =>
+                    {
+                        Some(i + 1)
+                    }
+                    _ => None,
+                };
+                if let Some(n) = comment {
+                    out.push(' ');
+                    out.push_str(tokens[n].text.trim_end());
+                    i = n + 1;
+                }
+                out.push('\n');
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// `!if`, `!elif`, `!else` or `!fi` in lowercase.
+fn branch_command(token: Token) -> Option<String> {
+    let name = token.text.to_lowercase();
+    if token.kind == TokenKind::Command && ["!if", "!elif", "!else", "!fi"].contains(&name.as_str()) {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+/// What separates a token from the previous one in the script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Gap {
+    None,
+    Space,
+    Newline,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Item<'a> {
+    token: Token<'a>,
+    gap: Gap,
+}
+
+impl<'a> Item<'a> {
+    fn is_word(&self, word: &str) -> bool {
+        self.token.kind == TokenKind::Word && self.token.text.eq_ignore_ascii_case(word)
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        self.token.kind == TokenKind::Punct && self.token.text == punct
+    }
+
+    fn is_comment(&self) -> bool {
+        matches!(self.token.kind, TokenKind::LineComment | TokenKind::BlockComment)
+    }
+}
+
+/// the tokens of a statement other than whitespace, with the gap before each.
+fn items<'a>(tokens: &[Token<'a>]) -> Vec<Item<'a>> {
+    let mut items = vec![];
+    let mut gap = Gap::None;
+    for token in tokens {
+        if token.kind == TokenKind::Whitespace {
+            gap = if token.text.contains('\n') { Gap::Newline } else { Gap::Space };
+            continue;
+        }
+        items.push(Item { token: *token, gap });
+        gap = Gap::None;
+    }
+    items
+}
+
+struct Writer {
+    out: String,
+    /// the indentation of the statement
+    indent: String,
+    /// a line comment was written last, so the next text goes on a new line
+    after_line_comment: bool,
+}
+
+impl Writer {
+    fn new(indent: String) -> Self {
+        Self {
+            out: String::new(),
+            indent,
+            after_line_comment: false,
+        }
+    }
+
+    fn push(&mut self, text: &str) {
+        if self.after_line_comment {
+            self.newline(1);
+        }
+        self.out.push_str(text);
+    }
+
+    fn space(&mut self) {
+        if !self.after_line_comment {
+            self.out.push(' ');
+        }
+    }
+
+    /// start a new line indented `depth` more than the statement.
+    fn newline(&mut self, depth: usize) {
+        self.after_line_comment = false;
+        self.out.push('\n');
+        self.out.push_str(&self.indent);
+        self.out.push_str(&"  ".repeat(depth));
+    }
+
+    fn item(&mut self, item: &Item, text: &str) {
+        self.push(text);
+        self.after_line_comment = item.token.kind == TokenKind::LineComment;
+    }
+
+    fn width(&self) -> usize {
+        self.indent.len() + self.out.chars().count()
+    }
+}
+
+fn write_statement(writer: &mut Writer, tokens: &[Token], kind: Option<&StatementKind>) {
+    let items = items(tokens);
+    let first = items[0].token;
+    let keyword = first.text.to_lowercase();
+    if first.kind == TokenKind::Command {
+        write_command(writer, &items);
+    } else if items[1..].iter().any(|item| item.is_comment()) {
+        write_lines(writer, &items, &keyword);
+    } else if keyword == "select" {
+        write_select(writer, &items);
+    } else if BYZER_KEYWORDS.contains(&keyword.as_str()) {
+        write_byzer(writer, &items, kind.map(options).unwrap_or_default());
+    } else {
+        write_sql(writer, &items, false);
+    }
+}
+
+/// `!` commands keep their arguments as they are, separated by one space.
+fn write_command(writer: &mut Writer, items: &[Item]) {
+    let name = branch_command(items[0].token).unwrap_or_else(|| items[0].token.text.to_string());
+    writer.push(&name);
+    for item in &items[1..] {
+        if item.gap != Gap::None {
+            writer.space();
+        }
+        writer.item(item, item.token.text);
+    }
+}
+
+/// statements with comments inside keep their line breaks, the following
+/// lines being indented under the first one.
+fn write_lines(writer: &mut Writer, items: &[Item], keyword: &str) {
+    let byzer = BYZER_KEYWORDS.contains(&keyword);
+    for (i, item) in items.iter().enumerate() {
+        match item.gap {
+            _ if i == 0 => {}
+            Gap::Newline => writer.newline(1),
+            Gap::Space => writer.space(),
+            Gap::None => {}
+        }
+        let text = if byzer { byzer_keyword(items, i) } else { sql_keyword(items, i) };
+        writer.item(item, &text);
+    }
+}
+
+/// `select ... as table`, on one line when it fits or else with each clause
+/// of the sql on its own line.
+fn write_select(writer: &mut Writer, items: &[Item]) {
+    let (sql, table) = items.split_at(items.len() - 2);
+    let mut line = Writer::new(writer.indent.clone());
+    write_sql(&mut line, sql, false);
+    line.push(" as ");
+    line.push(table[1].token.text);
+    if line.width() <= MAX_WIDTH && !line.out.contains('\n') {
+        writer.push(&line.out);
+        return;
+    }
+    write_sql(writer, sql, true);
+    writer.newline(0);
+    writer.push("as ");
+    writer.push(table[1].token.text);
+}
+
+/// sql keeps the spacing of the script, with the keywords in lowercase. With
+/// `split`, clauses outside of brackets start a new line.
+fn write_sql(writer: &mut Writer, items: &[Item], split: bool) {
+    let mut depth = 0usize;
+    for (i, item) in items.iter().enumerate() {
+        let text = sql_keyword(items, i);
+        if i > 0 {
+            if split && depth == 0 && starts_clause(items, i) {
+                writer.newline(1);
+            } else if item.gap != Gap::None {
+                writer.space();
+            }
+        }
+        writer.item(item, &text);
+        if item.is_punct("(") {
+            depth += 1;
+        } else if item.is_punct(")") {
+            depth = depth.saturating_sub(1);
+        }
+    }
+}
+
+fn starts_clause(items: &[Item], i: usize) -> bool {
+    let item = &items[i];
+    // `left(name, 3)` is a function
+    if items.get(i + 1).is_some_and(|next| next.is_punct("(")) {
+        return false;
+    }
+    if CLAUSE_KEYWORDS.iter().any(|k| item.is_word(k)) {
+        return true;
+    }
+    // `left outer join` starts at `left`
+    let is_join = |item: &Item| JOIN_KEYWORDS.iter().any(|k| item.is_word(k));
+    is_join(item)
+        && !is_join(&items[i - 1])
+        && items[i..].iter().take_while(|item| is_join(item)).any(|item| item.is_word("join"))
+}
+
+fn sql_keyword(items: &[Item], i: usize) -> String {
+    let token = items[i].token;
+    let after_dot = i > 0 && items[i - 1].is_punct(".");
+    if token.kind == TokenKind::Word && !after_dot && SQL_KEYWORDS.contains(&token.text.to_lowercase().as_str()) {
+        token.text.to_lowercase()
+    } else {
+        token.text.to_string()
+    }
+}
+
+fn byzer_keyword(items: &[Item], i: usize) -> String {
+    let token = items[i].token;
+    if token.kind != TokenKind::Word {
+        return token.text.to_string();
+    }
+    let lowercase = token.text.to_lowercase();
+    match lowercase.as_str() {
+        "partitionby" => String::from("partitionBy"),
+        "errorifexists" => String::from("errorIfExists"),
+        "as" | "where" | "options" | "and" | "overwrite" | "append" | "ignore" | "coalesce" => lowercase,
+        _ if i == 0 => lowercase,
+        _ => token.text.to_string(),
+    }
+}
+
+/// Byzer statements are written without spaces around `.` and `=`, and when
+/// too long their options go one per line:
+///
+/// ```text
+/// load csv.`/tmp/a.csv`
+/// where header="true"
+///   and delimiter=","
+/// as people;
+/// ```
+fn write_byzer(writer: &mut Writer, items: &[Item], options: &[OptionPair]) {
+    // the `where` or `options` right before the first key
+    let clause = options
+        .first()
+        .and_then(|first| items.iter().rposition(|item| item.token.start < first.key.span.start));
+    let (head, options, tail) = match (clause, options.last()) {
+        (Some(clause), Some(last)) => {
+            let end = items
+                .iter()
+                .position(|item| item.token.start >= last.value.span.end)
+                .unwrap_or(items.len());
+            let options = options.iter().map(|option| option_text(items, option)).collect();
+            (&items[..clause], options, &items[end..])
+        }
+        _ => (items, vec![], &items[items.len()..]),
+    };
+    let keyword = clause.map(|i| byzer_keyword(items, i)).unwrap_or_default();
+
+    let mut line = Writer::new(writer.indent.clone());
+    write_byzer_items(&mut line, head);
+    if !options.is_empty() {
+        line.push(&format!(" {} {}", keyword, options.join(" and ")));
+    }
+    if !tail.is_empty() {
+        line.space();
+        write_byzer_items(&mut line, tail);
+    }
+    if (line.width() <= MAX_WIDTH && !line.out.contains('\n')) || options.is_empty() {
+        writer.push(&line.out);
+        return;
+    }
+
+    write_byzer_items(writer, head);
+    for (i, option) in options.iter().enumerate() {
+        writer.newline(if i == 0 { 0 } else { 1 });
+        writer.push(&format!("{} {}", if i == 0 { keyword.as_str() } else { "and" }, option));
+    }
+    if !tail.is_empty() {
+        writer.newline(0);
+        write_byzer_items(writer, tail);
+    }
+}
+
+/// the options of the `where` or `options` clause of a statement.
+fn options(kind: &StatementKind) -> &[OptionPair] {
+    match kind {
+        StatementKind::Load { options, .. }
+        | StatementKind::Save { options, .. }
+        | StatementKind::Connect { options, .. }
+        | StatementKind::Set { options, .. }
+        | StatementKind::Train { options, .. }
+        | StatementKind::Register { options, .. }
+        | StatementKind::Include { options, .. } => options,
+        _ => &[],
+    }
+}
+
+/// `key=value`, without the whitespace the script may have inside the key.
+fn option_text(items: &[Item], option: &OptionPair) -> String {
+    let text = |span: &Range<usize>| {
+        items
+            .iter()
+            .filter(|item| span.contains(&item.token.start))
+            .map(|item| item.token.text)
+            .collect::<String>()
+    };
+    format!("{}={}", text(&option.key.span), text(&option.value.span))
+}
+
+fn write_byzer_items(writer: &mut Writer, items: &[Item]) {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 && byzer_gap(&items[i - 1], item) {
+            writer.space();
+        }
+        writer.item(item, &byzer_keyword(items, i));
+    }
+}
+
+/// no space around `.` and `=` nor before `,`, other punctuation keeps the
+/// spacing of the script, such as in `-1`.
+fn byzer_gap(prev: &Item, item: &Item) -> bool {
+    if prev.is_punct(".") || item.is_punct(".") || item.is_punct(",") || prev.is_punct("=") || item.is_punct("=") {
+        return false;
+    }
+    if (prev.token.kind == TokenKind::Punct && !prev.is_punct(",")) || item.token.kind == TokenKind::Punct {
+        return item.gap != Gap::None;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fixtures;
+
+    /// every `tests/fixtures/formatter/*.byzer` is formatted and compared with
+    /// the `.expected` file next to it, which must format to itself.
+    #[test]
+    fn fixtures() {
+        fixtures::check(
+            "formatter",
+            "expected",
+            |script| format(script).unwrap(),
+            |expected| match format(expected) {
+                Ok(again) if again == expected => Ok(()),
+                Ok(again) => Err(format!("not idempotent:\n{}", again)),
+                Err(e) => Err(e),
+            },
+        );
+    }
+
+    #[test]
+    fn refuse_invalid_scripts() {
+        assert_eq!(
+            format("select 1 as a;\nload csv as t;"),
+            Err(String::from("line 2, column 10: expected `.`, found `as`"))
+        );
+    }
+}
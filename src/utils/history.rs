@@ -0,0 +1,316 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{Local, TimeZone};
+use colored::*;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::conf::{byzer_dir, expand_home, open_private, ByzerConf};
+use crate::utils::jobs::format_elapsed;
+use crate::utils::progress_bar::ExecutingState;
+use crate::utils::trace::{redact_command, redact_script};
+
+/// One line entered in the shell, a line of the history file. Multi-line
+/// statements are kept as a single entry.
+#[derive(Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// unix time in milliseconds
+    pub time: u64,
+    pub script: String,
+    #[serde(rename = "durationMs", default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<ExecutingState>,
+    /// whether credentials were replaced, the script can't be executed again
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub redacted: bool,
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+/// Statements and commands entered in the shell, kept across sessions in a
+/// JSON Lines file. Statements with credentials are redacted, or skipped with
+/// `shell.history.secrets=skip`.
+pub struct History {
+    path: PathBuf,
+    size: usize,
+    dedup: bool,
+    skip_secrets: bool,
+    entries: Vec<HistoryEntry>,
+}
+
+impl History {
+    /// ```text
+    /// shell.history.file=~/.byzer/history
+    /// shell.history.size=1000
+    /// shell.history.dedup=true
+    /// shell.history.secrets=redact
+    /// ```
+    pub fn new(byzer_conf: &ByzerConf) -> Self {
+        let mut history = Self {
+            path: byzer_conf
+                .shell_config
+                .get("history.file")
+                .map(|path| expand_home(path))
+                .unwrap_or_else(|| byzer_dir().join("history")),
+            size: byzer_conf.shell_config_or("history.size", 1000usize),
+            dedup: byzer_conf.shell_config_or("history.dedup", true),
+            skip_secrets: byzer_conf.shell_config_or("history.secrets", String::from("redact")) == "skip",
+            entries: vec![],
+        };
+        history.load();
+        history
+    }
+
+    /// read the history file, and compact it if it holds more entries than kept.
+    fn load(&mut self) {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(_) => return,
+        };
+        let mut lines = 0;
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            lines += 1;
+            if let Ok(entry) = serde_json::from_str::<HistoryEntry>(line.as_str()) {
+                self.push(entry);
+            }
+        }
+        if lines > self.entries.len() {
+            let _ = self.save();
+        }
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let mut file = open_private(OpenOptions::new().write(true).truncate(true), &self.path)?;
+        for entry in &self.entries {
+            writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, entry: HistoryEntry) {
+        if self.dedup {
+            self.entries.retain(|e| e.script != entry.script);
+        }
+        self.entries.push(entry);
+        if self.entries.len() > self.size {
+            let excess = self.entries.len() - self.size;
+            self.entries.drain(..excess);
+        }
+    }
+
+    /// the scripts of the entries, oldest first.
+    pub fn scripts(&self) -> Vec<String> {
+        self.entries.iter().map(|e| e.script.clone()).collect()
+    }
+
+    /// the script of the `n`th entry, one based as listed by `:history`, to
+    /// execute again.
+    pub fn get(&self, n: usize) -> Result<&str, String> {
+        let entry = n
+            .checked_sub(1)
+            .and_then(|i| self.entries.get(i))
+            .ok_or_else(|| format!("no history entry {}", n))?;
+        executable(entry)
+    }
+
+    /// the script of the last entry which is not a `:` command.
+    pub fn last_statement(&self) -> Option<&str> {
+        self.last_statement_entry().map(|e| e.script.as_str())
+    }
+
+    /// the script of the last entry which is not a `:` command, to execute
+    /// again.
+    pub fn last_executable_statement(&self) -> Result<Option<&str>, String> {
+        self.last_statement_entry().map(executable).transpose()
+    }
+
+    fn last_statement_entry(&self) -> Option<&HistoryEntry> {
+        self.entries.iter().rev().find(|e| !e.script.starts_with(':'))
+    }
+
+    /// add `script` and append it to the history file, with its credentials and
+    /// the values given to `:var` redacted. `duration` and `status` are only
+    /// known for statements which ran to the end.
+    pub fn record(&mut self, script: &str, duration: Option<Duration>, status: Option<ExecutingState>) {
+        let script = script.trim();
+        if script.is_empty() {
+            return;
+        }
+        let redacted = if script.starts_with(':') {
+            redact_command(script)
+        } else {
+            redact_script(script)
+        };
+        if redacted != script && self.skip_secrets {
+            return;
+        }
+
+        let entry = HistoryEntry {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            redacted: redacted != script,
+            script: redacted,
+            duration_ms: duration.map(|d| d.as_millis() as u64),
+            status,
+        };
+        let line = serde_json::to_string(&entry);
+        self.push(entry);
+
+        if let Some(parent) = self.path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let (Ok(mut file), Ok(line)) = (open_private(OpenOptions::new().append(true), &self.path), line) {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+
+    /// list the entries whose script contains `pattern`, ignoring case.
+    pub fn print(&self, pattern: &str) {
+        let pattern = pattern.to_lowercase();
+
+        let mut found = false;
+        for (i, entry) in self.entries.iter().enumerate() {
+            if !entry.script.to_lowercase().contains(&pattern) {
+                continue;
+            }
+            found = true;
+            let time = Local
+                .timestamp_millis_opt(entry.time as i64)
+                .single()
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default();
+            let duration = entry
+                .duration_ms
+                .map(|ms| format_duration(Duration::from_millis(ms)))
+                .unwrap_or_default();
+            let status = match entry.status {
+                Some(ExecutingState::Succeeded) => "✅",
+                Some(ExecutingState::Failed) => "❌",
+                Some(ExecutingState::Cancelled) => "🚫",
+                None => "  ",
+            };
+            // continuation lines are aligned under the first line of the script
+            let header = format!("{:>5}  {}  {:>7}  {} ", i + 1, time, duration, status);
+            let emoji_width = if entry.status.is_some() { 1 } else { 0 };
+            let indent = " ".repeat(header.chars().count() + emoji_width);
+            let mut lines = entry.script.lines();
+            println!("{}{}", header.dimmed(), lines.next().unwrap_or_default());
+            for line in lines {
+                println!("{}{}", indent, line);
+            }
+        }
+
+        if found {
+            println!("{}", "(`:rerun <n>` to execute an entry again)".dimmed());
+        } else {
+            println!("No matching history.");
+        }
+    }
+}
+
+fn executable(entry: &HistoryEntry) -> Result<&str, String> {
+    if entry.redacted {
+        return Err(String::from("its credentials were redacted, it can't be executed again"));
+    }
+    Ok(entry.script.as_str())
+}
+
+fn format_duration(duration: Duration) -> String {
+    if duration < Duration::from_secs(1) {
+        format!("{}ms", duration.as_millis())
+    } else {
+        format_elapsed(duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fixtures::TempDir;
+    use std::env;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+
+    /// a history whose file is in a temp directory which lives as long as the
+    /// guard.
+    fn new_history(name: &str, size: usize, skip_secrets: bool) -> (TempDir, History) {
+        let dir = TempDir::new(&format!("history-{}", name));
+        let history = History {
+            path: dir.path().join("history"),
+            size,
+            dedup: true,
+            skip_secrets,
+            entries: vec![],
+        };
+        (dir, history)
+    }
+
+    #[test]
+    fn redact_or_skip_secrets() {
+        let script = "connect jdbc where url=\"jdbc:mysql://h/db\" and password=\"pw\" as db;";
+        let (_dir, mut history) = new_history("redact", 10, false);
+        history.record(script, None, None);
+        history.record("select 1 as a;", None, None);
+        assert_eq!(history.scripts()[0], "connect jdbc where url=\"jdbc:mysql://h/db\" and password=\"******\" as db;");
+        assert!(history.get(1).is_err());
+        assert_eq!(history.get(2), Ok("select 1 as a;"));
+        assert_eq!(history.get(3), Err(String::from("no history entry 3")));
+
+        // the redacted entry is still refused once read back from the file
+        history.record(script, None, None);
+        let mut reloaded = History {
+            entries: vec![],
+            ..history
+        };
+        reloaded.load();
+        assert!(reloaded.last_executable_statement().is_err());
+        #[cfg(unix)]
+        assert_eq!(fs::metadata(&reloaded.path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        let (_dir, mut history) = new_history("skip", 10, true);
+        history.record(script, None, None);
+        assert!(history.scripts().is_empty());
+    }
+
+    #[test]
+    fn redact_var_values() {
+        let (_dir, mut history) = new_history("var", 10, false);
+        history.record(":var password s3cr3t", None, None);
+        history.record(":var password", None, None);
+        assert_eq!(history.scripts(), vec![":var password ******", ":var password"]);
+        assert!(!fs::read_to_string(&history.path).unwrap().contains("s3cr3t"));
+    }
+
+    #[test]
+    fn dedup_and_cap() {
+        let (_dir, mut history) = new_history("cap", 3, false);
+        for script in ["select 1 as a;", "select 2 as a;", "select 1 as a;", "select 3 as a;", "select 4 as a;"] {
+            history.record(script, Some(Duration::from_millis(5)), Some(ExecutingState::Succeeded));
+        }
+        let expected = vec!["select 1 as a;", "select 3 as a;", "select 4 as a;"];
+        assert_eq!(history.scripts(), expected);
+
+        // the file holds every line appended, it is compacted when loaded
+        let mut reloaded = History {
+            entries: vec![],
+            ..history
+        };
+        reloaded.load();
+        assert_eq!(reloaded.scripts(), expected);
+        assert_eq!(fs::read_to_string(&reloaded.path).unwrap().lines().count(), 3);
+    }
+
+    #[test]
+    fn expand_history_path() {
+        let home = env::var("HOME").unwrap_or_else(|_| String::from("."));
+        assert_eq!(expand_home("~/h"), PathBuf::from(&home).join("h"));
+        assert_eq!(expand_home("/tmp/~h"), PathBuf::from("/tmp/~h"));
+    }
+}
@@ -0,0 +1,252 @@
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::utils::conf::ByzerConf;
+use crate::utils::lexer::{self, TokenKind};
+use crate::utils::lint::line_and_column;
+use crate::utils::parser::{self, Statement, StatementKind, Value};
+
+/// Where the lines of a script with inlined includes come from.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SourceMap {
+    /// the first line of each piece in the script, the file it comes from
+    /// (`None` for the script itself) and its first line there, all one based
+    segments: Vec<(usize, Option<PathBuf>, usize)>,
+}
+
+impl SourceMap {
+    /// the file and line `line` of the script comes from.
+    pub fn locate(&self, line: usize) -> (Option<&Path>, usize) {
+        match self.segments.iter().rev().find(|(start, _, _)| *start <= line) {
+            Some((start, origin, origin_line)) => (origin.as_deref(), origin_line + line - start),
+            None => (None, line),
+        }
+    }
+
+    /// the map of a script made of pieces of the mapped one, each given by its
+    /// first line and its number of lines.
+    pub fn pieces(&self, pieces: &[(usize, usize)]) -> SourceMap {
+        let mut segments: Vec<(usize, Option<PathBuf>, usize)> = vec![];
+        let mut out_line = 1;
+        for &(first, lines) in pieces {
+            for line in first..first + lines {
+                let (origin, origin_line) = self.locate(line);
+                let follows = segments.last().is_some_and(|(start, last_origin, last_line)| {
+                    last_origin.as_deref() == origin && last_line + (out_line - start) == origin_line
+                });
+                if !follows {
+                    segments.push((out_line, origin.map(Path::to_path_buf), origin_line));
+                }
+                out_line += 1;
+            }
+        }
+        SourceMap { segments }
+    }
+
+    /// account for `lines` lines added before the script.
+    pub fn shift(&mut self, lines: usize) {
+        for segment in &mut self.segments {
+            segment.0 += lines;
+        }
+    }
+}
+
+/// With `shell.include.local=true`, `include local.`path`;` is replaced by the
+/// content of the file before a script is sent, the path being relative to
+/// `shell.include.root`, the current directory by default. The included
+/// files are resolved the same way, `.byzer` and `.mlsql` may be left out.
+#[derive(Debug, Clone, Default)]
+pub struct IncludeResolver {
+    pub enabled: bool,
+    root: PathBuf,
+}
+
+impl IncludeResolver {
+    pub fn new(byzer_conf: &ByzerConf) -> Self {
+        Self {
+            enabled: byzer_conf.shell_config_or("include.local", false),
+            root: PathBuf::from(byzer_conf.shell_config_or("include.root", String::from("."))),
+        }
+    }
+
+    /// `script` with its local includes inlined, and where its lines come
+    /// from if anything was inlined.
+    pub fn resolve(&self, script: &str) -> Result<(String, Option<SourceMap>), String> {
+        let mut output = Output::default();
+        self.inline(script, None, &mut vec![], &mut output)?;
+        if !output.inlined {
+            return Ok((script.to_string(), None));
+        }
+        Ok((output.text, Some(SourceMap { segments: output.segments })))
+    }
+
+    /// append `script`, read from `origin`, to `output` with its includes
+    /// inlined. `stack` holds the files being included, to detect cycles.
+    fn inline(
+        &self,
+        script: &str,
+        origin: Option<&Path>,
+        stack: &mut Vec<PathBuf>,
+        output: &mut Output,
+    ) -> Result<(), String> {
+        let mut includes = vec![];
+        local_includes(&parser::parse(script).statements, &mut includes);
+        output.inlined |= !includes.is_empty();
+
+        let mut pos = 0;
+        for (statement, path) in includes {
+            output.push(&script[pos..statement.start], origin, line_and_column(script, pos).0);
+            let location = || {
+                let (line, column) = line_and_column(script, statement.start);
+                match origin {
+                    Some(origin) => format!("{}:{}:{}", origin.display(), line, column),
+                    None => format!("line {}, column {}", line, column),
+                }
+            };
+
+            let file = self.find(&path).ok_or_else(|| format!("{}: no local file for `{}`", location(), path))?;
+            let canonical = file.canonicalize().unwrap_or_else(|_| file.clone());
+            if stack.contains(&canonical) {
+                let cycle = stack
+                    .iter()
+                    .chain([&canonical])
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>();
+                return Err(format!("{}: include cycle {}", location(), cycle.join(" -> ")));
+            }
+            let content = fs::read_to_string(&file)
+                .map_err(|e| format!("{}: fail to read {}: {}", location(), file.display(), e))?;
+
+            stack.push(canonical);
+            self.inline(&terminated(&content), Some(&file), stack, output)?;
+            stack.pop();
+            pos = statement.end;
+        }
+        output.push(&script[pos..], origin, line_and_column(script, pos).0);
+        Ok(())
+    }
+
+    fn find(&self, path: &str) -> Option<PathBuf> {
+        let path = self.root.join(path);
+        ["", "byzer", "mlsql"]
+            .iter()
+            .map(|ext| if ext.is_empty() { path.clone() } else { path.with_extension(ext) })
+            .find(|p| p.is_file())
+    }
+}
+
+/// `script` with a `;` after its last statement if it has none, so that it
+/// is not joined to the statement following the include.
+fn terminated(script: &str) -> Cow<'_, str> {
+    match lexer::tokenize(script).iter().rev().find(|t| !t.is_trivia()) {
+        Some(last) if last.kind != TokenKind::Semicolon => {
+            Cow::Owned(format!("{};{}", &script[..last.end()], &script[last.end()..]))
+        }
+        _ => Cow::Borrowed(script),
+    }
+}
+
+/// the spans and paths of the `include local.`path`` statements, those of
+/// `!if` blocks included.
+fn local_includes(statements: &[Statement], includes: &mut Vec<(std::ops::Range<usize>, String)>) {
+    for statement in statements {
+        match &statement.kind {
+            StatementKind::Include { source, .. } if source.format.node.eq_ignore_ascii_case("local") => {
+                if let Value::Quoted(path) | Value::String(path) | Value::Word(path) = &source.path.node {
+                    includes.push((statement.span.clone(), path.clone()));
+                }
+            }
+            StatementKind::If { branches, .. } => {
+                for branch in branches {
+                    local_includes(&branch.body, includes);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Default)]
+struct Output {
+    inlined: bool,
+    text: String,
+    segments: Vec<(usize, Option<PathBuf>, usize)>,
+}
+
+impl Output {
+    /// append `text`, which starts at `line` of `origin`, on a new line.
+    fn push(&mut self, text: &str, origin: Option<&Path>, line: usize) {
+        if text.trim().is_empty() {
+            return;
+        }
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.text.push('\n');
+        }
+        let start = self.text.matches('\n').count() + 1;
+        self.segments.push((start, origin.map(Path::to_path_buf), line));
+        self.text.push_str(text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fixtures::TempDir;
+
+    /// the resolver of a temp directory holding `files`, which lives as long
+    /// as the guard.
+    fn resolver(name: &str, files: &[(&str, &str)]) -> (TempDir, IncludeResolver) {
+        let dir = TempDir::new(&format!("include-{}", name));
+        let root = dir.path().to_path_buf();
+        fs::create_dir_all(root.join("lib")).unwrap();
+        for (name, content) in files {
+            fs::write(root.join(name), content).unwrap();
+        }
+        (dir, IncludeResolver { enabled: true, root })
+    }
+
+    #[test]
+    fn inline_includes() {
+        let (_dir, resolver) = resolver("inline", &[
+            ("lib/a.byzer", "-- a\nload csv.`/tmp/a.csv` as a;\ninclude local.`lib/b`;\n"),
+            ("lib/b.byzer", "select 1 as b;\nselect 2 as c -- no `;`\n"),
+        ]);
+        let script = "set x = 1;\ninclude local.`lib/a.byzer`;\nselect * from a as d;";
+        let (resolved, source_map) = resolver.resolve(script).unwrap();
+        assert_eq!(
+            resolved,
+            "set x = 1;\n-- a\nload csv.`/tmp/a.csv` as a;\nselect 1 as b;\nselect 2 as c; -- no `;`\n\nselect * from a as d;"
+        );
+        let source_map = source_map.unwrap();
+        let locate = |line| {
+            let (origin, line) = source_map.locate(line);
+            (origin.map(|p| p.file_name().unwrap().to_str().unwrap().to_string()), line)
+        };
+        assert_eq!(locate(1), (None, 1));
+        assert_eq!(locate(3), (Some(String::from("a.byzer")), 2));
+        assert_eq!(locate(5), (Some(String::from("b.byzer")), 2));
+        assert_eq!(locate(7), (None, 3));
+
+        assert_eq!(resolver.resolve("select 1 as a;").unwrap(), (String::from("select 1 as a;"), None));
+
+        // `set x = 1;` followed by `select 2 as c;`, as sent in split mode
+        let pieces = source_map.pieces(&[(1, 1), (5, 1)]);
+        assert_eq!(pieces.locate(1), (None, 1));
+        assert_eq!(pieces.locate(2).1, 2);
+        assert_eq!(pieces.locate(2).0.and_then(|p| p.file_name()), Some("b.byzer".as_ref()));
+    }
+
+    #[test]
+    fn detect_cycles() {
+        let (_dir, resolver) = resolver("cycles", &[
+            ("lib/x.byzer", "include local.`lib/y`;"),
+            ("lib/y.byzer", "select 1 as y;\ninclude local.`lib/x`;"),
+            ("lib/z.byzer", ""),
+        ]);
+        let error = resolver.resolve("include local.`lib/x`;").unwrap_err();
+        assert!(error.contains("y.byzer:2:1: include cycle"), "{}", error);
+        let error = resolver.resolve("\n  include local.`lib/missing`;").unwrap_err();
+        assert_eq!(error, "line 2, column 3: no local file for `lib/missing`");
+    }
+}
@@ -0,0 +1,57 @@
+use lazy_static::lazy_static;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+
+lazy_static! {
+    /// the sender of the query waiting for Ctrl-C, if any.
+    static ref LISTENER: Mutex<Option<Sender<()>>> = Mutex::new(None);
+}
+
+/// Ctrl-C was pressed while no query was listening for it.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// install the SIGINT handler. While a query is running, Ctrl-C is delivered to
+/// its `InterruptListener` so the query can be cancelled, otherwise it is only
+/// recorded, see `take_interrupted`, so that the shell always exits through
+/// the path which stops the engine. The line editor reads Ctrl-C as a key, so
+/// this handler is not involved while the prompt is shown.
+pub fn install_handler() {
+    ctrlc::set_handler(|| match LISTENER.lock().unwrap().as_ref() {
+        Some(tx) => {
+            let _ = tx.send(());
+        }
+        None => INTERRUPTED.store(true, Ordering::SeqCst),
+    })
+    .expect("Fail to install the Ctrl-C handler");
+}
+
+/// whether Ctrl-C was pressed while nothing listened for it, such as while
+/// the engine is starting. Reading it clears it.
+pub fn take_interrupted() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
+/// receives Ctrl-C while it is alive.
+pub struct InterruptListener {
+    rx: Receiver<()>,
+}
+
+impl InterruptListener {
+    pub fn new() -> Self {
+        let (tx, rx) = channel();
+        *LISTENER.lock().unwrap() = Some(tx);
+        Self { rx }
+    }
+
+    /// whether Ctrl-C was pressed since the last call.
+    pub fn interrupted(&self) -> bool {
+        self.rx.try_recv().is_ok()
+    }
+}
+
+impl Drop for InterruptListener {
+    fn drop(&mut self) {
+        *LISTENER.lock().unwrap() = None;
+    }
+}
@@ -0,0 +1,418 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::utils::callback::CallbackServer;
+use crate::utils::conf::ByzerConf;
+use crate::utils::diagnostic::print_error;
+use crate::utils::engine::EngineClient;
+use crate::utils::include::SourceMap;
+use crate::utils::interrupt::InterruptListener;
+use crate::utils::lexer::{self, TokenKind};
+use crate::utils::progress_bar::ExecutingProgressBar;
+use crate::utils::{print_as_table, print_value};
+
+pub enum JobState {
+    Running,
+    Finished {
+        status: u16,
+        result: String,
+        elapsed: Duration,
+    },
+}
+
+/// a script submitted with `:async` or a trailing `&`.
+pub struct Job {
+    pub name: String,
+    pub script: String,
+    /// where the lines of the script come from, if it has inlined includes
+    source_map: Option<SourceMap>,
+    pub started: Instant,
+    pub state: JobState,
+    /// whether the user has been told that the job finished
+    reported: bool,
+    /// the polls of the engine's jobs in a row which didn't list the job
+    missed_polls: u32,
+}
+
+type Jobs = Arc<Mutex<BTreeMap<usize, Job>>>;
+
+/// Runs scripts as async jobs of the engine, with `async=true`, so the prompt
+/// stays usable, and keeps their results until the user fetches them. The
+/// engine posts the result of a job to the callback server of the shell:
+///
+/// ```text
+/// # where the callback server listens, and the url the engine reaches it at
+/// shell.jobs.callback.bind=127.0.0.1:0
+/// shell.jobs.callback.url=http://127.0.0.1:<port>/
+/// # how often the engine is asked which jobs are still running
+/// shell.jobs.poll.interval=5s
+/// ```
+///
+/// A job which is no longer running on the engine although its result never
+/// arrived is reported as failed. Jobs are referred to either by their
+/// sequence number, shown as `[n]`, or by their job name.
+pub struct JobManager {
+    engine: Arc<EngineClient>,
+    jobs: Jobs,
+    /// `None` if the callback server can not listen, async jobs are disabled
+    callback: Option<CallbackServer>,
+}
+
+impl JobManager {
+    pub fn new(engine: Arc<EngineClient>, byzer_conf: &ByzerConf) -> Self {
+        let jobs: Jobs = Arc::new(Mutex::new(BTreeMap::new()));
+        let bind = byzer_conf.shell_config_or("jobs.callback.bind", String::from("127.0.0.1:0"));
+        let url = byzer_conf.shell_config.get("jobs.callback.url").cloned();
+        let callback = {
+            let jobs = Arc::clone(&jobs);
+            let max_body = byzer_conf.shell_config_or("jobs.callback.max.body", 64 * 1024);
+            CallbackServer::start(bind.as_str(), url, max_body, move |params| finish(&jobs, &params))
+        };
+        let callback = match callback {
+            Ok(callback) => Some(callback),
+            Err(e) => {
+                println!("Async jobs are disabled, fail to listen on {}: {}", bind, e);
+                None
+            }
+        };
+
+        let poll_interval = byzer_conf.shell_duration_or("jobs.poll.interval", Duration::from_secs(5));
+        {
+            let engine = Arc::clone(&engine);
+            let jobs = Arc::clone(&jobs);
+            thread::spawn(move || loop {
+                sleep(poll_interval);
+                poll(&engine, &jobs);
+            });
+        }
+
+        Self { engine, jobs, callback }
+    }
+
+    /// submit `script` as an async job, returns its sequence number.
+    pub fn submit(&self, script: &str, source_map: Option<SourceMap>) -> Option<usize> {
+        let callback = match &self.callback {
+            Some(callback) => callback,
+            None => {
+                println!("Async jobs are disabled, the callback server is not listening.");
+                return None;
+            }
+        };
+        let name = self.engine.new_job_name();
+        let url = callback.url(name.as_str());
+        let id = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let id = jobs.keys().last().map(|k| k + 1).unwrap_or(1);
+            jobs.insert(
+                id,
+                Job {
+                    name: name.clone(),
+                    script: script.to_string(),
+                    source_map,
+                    started: Instant::now(),
+                    state: JobState::Running,
+                    reported: false,
+                    missed_polls: 0,
+                },
+            );
+            id
+        };
+
+        // the engine answers once the job is started, its result comes later
+        let (status, result) = self
+            .engine
+            .run_job(script, name.as_str(), &[("async", "true"), ("callback", url.as_str())]);
+        if status != 200 {
+            let job = self.jobs.lock().unwrap().remove(&id);
+            print_error(script, result.as_str(), job.and_then(|job| job.source_map).as_ref());
+            return None;
+        }
+
+        println!("[{}] submitted as {}", id, name);
+        Some(id)
+    }
+
+    /// print a line for every job which finished since the last report.
+    pub fn report_finished(&self) {
+        for (id, job) in self.jobs.lock().unwrap().iter_mut() {
+            if let (JobState::Finished { status, elapsed, .. }, false) = (&job.state, job.reported) {
+                let mark = if *status == 200 { "✅" } else { "❌" };
+                println!(
+                    "[{}] {} finished {} {}, `:result {}` to show it.",
+                    id,
+                    job.name,
+                    mark,
+                    format_elapsed(*elapsed),
+                    id
+                );
+                job.reported = true;
+            }
+        }
+    }
+
+    /// the number of async jobs which have not finished yet.
+    pub fn running(&self) -> usize {
+        self.jobs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|job| matches!(job.state, JobState::Running))
+            .count()
+    }
+
+    /// kill the async jobs which have not finished yet, on exit.
+    pub fn kill_running(&self) {
+        let running = self
+            .jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, job)| matches!(job.state, JobState::Running))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in running {
+            self.kill(id);
+        }
+    }
+
+    /// run `f` with the sequence number of the job `job_ref` refers to.
+    pub fn with_job<F: FnOnce(usize)>(&self, job_ref: &str, f: F) {
+        match self.find(job_ref) {
+            Some(id) => f(id),
+            None if job_ref.is_empty() => println!("A job number or name is required."),
+            None => println!("No such job: {}", job_ref),
+        }
+    }
+
+    fn find(&self, job_ref: &str) -> Option<usize> {
+        let jobs = self.jobs.lock().unwrap();
+        if let Ok(id) = job_ref.trim_start_matches('[').trim_end_matches(']').parse::<usize>() {
+            if jobs.contains_key(&id) {
+                return Some(id);
+            }
+        }
+        jobs.iter()
+            .find(|(_, job)| job.name == job_ref)
+            .map(|(id, _)| *id)
+    }
+
+    /// list the jobs running on the engine as reported by `!show jobs;`,
+    /// followed by the async jobs of this shell that have finished.
+    pub fn print_jobs(&self) {
+        let (status, res) = self.engine.run_query("!show jobs;");
+        if status != 200 {
+            print_error("", res.as_str(), None);
+            return;
+        }
+        let engine_jobs = serde_json::from_str::<Value>(res.as_str())
+            .ok()
+            .and_then(|v| v.as_array().cloned())
+            .unwrap_or_default();
+
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
+        let jobs = self.jobs.lock().unwrap();
+        let mut rows = vec![];
+        for engine_job in &engine_jobs {
+            let job_name = engine_job["jobName"].as_str().unwrap_or_default();
+            let id = jobs
+                .iter()
+                .find(|(_, job)| job.name == job_name)
+                .map(|(id, _)| id.to_string())
+                .unwrap_or_default();
+            let elapsed = engine_job["startTime"]
+                .as_u64()
+                .map(|start| format_elapsed(Duration::from_millis(now_ms.saturating_sub(start))))
+                .unwrap_or_default();
+            rows.push(json!({
+                "id": id,
+                "jobName": job_name,
+                "owner": engine_job["owner"],
+                "state": "running",
+                "elapsed": elapsed,
+                "content": abbreviate(engine_job["jobContent"].as_str().unwrap_or_default()),
+            }));
+        }
+        for (id, job) in jobs.iter() {
+            if let JobState::Finished { status, elapsed, .. } = &job.state {
+                rows.push(json!({
+                    "id": id.to_string(),
+                    "jobName": job.name,
+                    "owner": "",
+                    "state": if *status == 200 { "finished" } else { "failed" },
+                    "elapsed": format_elapsed(*elapsed),
+                    "content": abbreviate(job.script.as_str()),
+                }));
+            }
+        }
+
+        if rows.is_empty() {
+            println!("No jobs.");
+        } else {
+            print_value(&Value::Array(rows));
+        }
+    }
+
+    /// block until the job finishes, Ctrl-C stops waiting but leaves the job running.
+    pub fn wait(&self, id: usize) {
+        let listener = InterruptListener::new();
+        let mut pb = ExecutingProgressBar::new();
+        let monitor_handler = pb.start_monitor(format!("Waiting for [{}]:", id));
+        loop {
+            if let Some(JobState::Finished { status, .. }) =
+                self.jobs.lock().unwrap().get(&id).map(|job| &job.state)
+            {
+                pb.send_finish_signal(*status == 200);
+                break;
+            }
+            if listener.interrupted() {
+                pb.send_cancel_signal();
+                monitor_handler.join().unwrap();
+                return;
+            }
+            sleep(Duration::from_millis(100));
+        }
+        monitor_handler.join().unwrap();
+        self.print_result(id);
+    }
+
+    pub fn kill(&self, id: usize) {
+        let name = match self.jobs.lock().unwrap().get(&id) {
+            Some(Job { name, state: JobState::Running, .. }) => Some(name.clone()),
+            Some(job) => {
+                println!("[{}] {} has already finished.", id, job.name);
+                None
+            }
+            None => None,
+        };
+        if let Some(name) = name {
+            let (status, res) = self.engine.kill_job(name.as_str());
+            if status == 200 {
+                println!("[{}] {} killed.", id, name);
+            } else {
+                print_error("", res.as_str(), None);
+            }
+        }
+    }
+
+    pub fn print_result(&self, id: usize) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(&id) {
+            match &job.state {
+                JobState::Running => println!(
+                    "[{}] {} is still running, {} elapsed.",
+                    id,
+                    job.name,
+                    format_elapsed(job.started.elapsed())
+                ),
+                JobState::Finished { status, result, .. } => {
+                    job.reported = true;
+                    if *status == 200 {
+                        print_as_table(result.as_str());
+                    } else {
+                        print_error(job.script.as_str(), result.as_str(), job.source_map.as_ref());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// record the result the engine posted to the callback url of a job.
+fn finish(jobs: &Jobs, params: &HashMap<String, String>) {
+    let name = params.get("jobName").map(String::as_str).unwrap_or_default();
+    let (status, result) = match params.get("stat").map(String::as_str) {
+        Some("succeeded") => (200, params.get("res")),
+        _ => (500, params.get("msg")),
+    };
+    let mut jobs = jobs.lock().unwrap();
+    if let Some(job) = jobs.values_mut().find(|job| job.name == name) {
+        if matches!(job.state, JobState::Running) {
+            job.state = JobState::Finished {
+                status,
+                result: result.cloned().unwrap_or_default(),
+                elapsed: job.started.elapsed(),
+            };
+        }
+    }
+}
+
+/// fail the running jobs the engine didn't list twice in a row, their result
+/// will not arrive.
+fn poll(engine: &EngineClient, jobs: &Jobs) {
+    if !jobs.lock().unwrap().values().any(|job| matches!(job.state, JobState::Running)) {
+        return;
+    }
+    let (status, res) = engine.run_query("!show jobs;");
+    if status != 200 {
+        return;
+    }
+    let listed = serde_json::from_str::<Value>(res.as_str())
+        .ok()
+        .and_then(|v| v.as_array().cloned())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|job| job["jobName"].as_str().map(String::from))
+        .collect::<HashSet<_>>();
+
+    for job in jobs.lock().unwrap().values_mut() {
+        if !matches!(job.state, JobState::Running) {
+            continue;
+        }
+        if listed.contains(&job.name) {
+            job.missed_polls = 0;
+            continue;
+        }
+        job.missed_polls += 1;
+        if job.missed_polls >= 2 {
+            job.state = JobState::Finished {
+                status: 500,
+                result: String::from(
+                    "The job is no longer running on the engine, but its result never reached the shell. \
+                     Check that the engine can reach `shell.jobs.callback.url`.",
+                ),
+                elapsed: job.started.elapsed(),
+            };
+        }
+    }
+}
+
+/// the script of `input` if it ends with the `&` which submits it as an async
+/// job, that is a `&` outside strings and comments.
+pub fn async_script(input: &str) -> Option<&str> {
+    let tokens = lexer::tokenize(input);
+    let last = tokens.iter().rev().find(|t| !t.is_trivia())?;
+    if last.kind == TokenKind::Punct && last.text == "&" {
+        Some(input[..last.start].trim_end())
+    } else {
+        None
+    }
+}
+
+pub fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h {}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+/// the first line of a script, cut to fit in a table cell.
+pub fn abbreviate(script: &str) -> String {
+    let first_line = script.trim().lines().next().unwrap_or_default();
+    if first_line.chars().count() > 40 {
+        format!("{}...", first_line.chars().take(40).collect::<String>())
+    } else {
+        first_line.to_string()
+    }
+}
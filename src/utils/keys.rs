@@ -0,0 +1,242 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use rustyline::{
+    Cmd, CompletionType, ConditionalEventHandler, Config, EditMode, Editor, Event, EventContext,
+    EventHandler, KeyCode, KeyEvent, Modifiers, Movement, RepeatCount,
+};
+
+use crate::utils::conf::ByzerConf;
+use crate::utils::external_editor::ExternalEditor;
+use crate::utils::lexer;
+use crate::utils::shrust::{EditHelper, LineBuffer};
+use crate::utils::toggle_output_format;
+
+/// What a key can be bound to in `[shell.keys]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAction {
+    /// submit only the statement under the cursor
+    ExecuteStatement,
+    /// insert a newline without submitting
+    Newline,
+    /// switch to the next output format
+    ToggleFormat,
+    /// open the line in the external editor
+    OpenEditor,
+}
+
+impl FromStr for KeyAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "execute-statement" => Ok(KeyAction::ExecuteStatement),
+            "newline" => Ok(KeyAction::Newline),
+            "toggle-format" => Ok(KeyAction::ToggleFormat),
+            "open-editor" => Ok(KeyAction::OpenEditor),
+            _ => Err(format!("unknown key action: {}", s)),
+        }
+    }
+}
+
+/// parse keys such as `ctrl-x ctrl-e`, `alt-enter` or `f2`, the keys of a
+/// sequence being separated by spaces.
+pub fn parse_keys(s: &str) -> Result<Vec<KeyEvent>, String> {
+    let keys = s.split_whitespace().map(parse_key).collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() {
+        return Err(String::from("no key"));
+    }
+    Ok(keys)
+}
+
+fn parse_key(s: &str) -> Result<KeyEvent, String> {
+    let mut mods = Modifiers::NONE;
+    let mut rest = s;
+    loop {
+        let lower = rest.to_lowercase();
+        if lower.starts_with("ctrl-") || lower.starts_with("c-") {
+            mods |= Modifiers::CTRL;
+        } else if lower.starts_with("alt-") || lower.starts_with("meta-") || lower.starts_with("m-") {
+            mods |= Modifiers::ALT;
+        } else if lower.starts_with("shift-") || lower.starts_with("s-") {
+            mods |= Modifiers::SHIFT;
+        } else {
+            break;
+        }
+        rest = rest.split_once('-').map(|(_, key)| key).unwrap_or_default();
+    }
+
+    let code = match rest.to_lowercase().as_str() {
+        "enter" | "return" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "esc" | "escape" => KeyCode::Esc,
+        "backspace" => KeyCode::Backspace,
+        "delete" | "del" => KeyCode::Delete,
+        "space" => KeyCode::Char(' '),
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        key if key.len() > 1 && key.starts_with('f') => match key[1..].parse() {
+            Ok(n) if (1..=12).contains(&n) => KeyCode::F(n),
+            _ => return Err(format!("unknown key: {}", s)),
+        },
+        _ => {
+            let mut chars = rest.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => KeyCode::Char(c),
+                _ => return Err(format!("unknown key: {}", s)),
+            }
+        }
+    };
+    Ok(KeyEvent::normalize(KeyEvent(code, mods)))
+}
+
+/// The edit mode, the completion type and the key bindings of the line
+/// editor, read from the `[shell.keys]` section of the conf file:
+///
+/// ```text
+/// [shell.keys]
+/// mode=vi
+/// completion=list
+/// ctrl-x ctrl-e=open-editor
+/// alt-enter=newline
+/// ctrl-j=execute-statement
+/// f2=toggle-format
+/// ```
+pub struct KeyConfig {
+    pub edit_mode: EditMode,
+    pub completion_type: CompletionType,
+    pub bindings: Vec<(Vec<KeyEvent>, KeyAction)>,
+}
+
+impl Default for KeyConfig {
+    fn default() -> Self {
+        Self {
+            edit_mode: EditMode::Emacs,
+            completion_type: CompletionType::Circular,
+            bindings: vec![(
+                vec![KeyEvent::ctrl('X'), KeyEvent::ctrl('E')],
+                KeyAction::OpenEditor,
+            )],
+        }
+    }
+}
+
+impl KeyConfig {
+    /// malformed settings are reported and skipped.
+    pub fn new(byzer_conf: &ByzerConf) -> Self {
+        let mut key_config = KeyConfig::default();
+        let mut settings = byzer_conf
+            .shell_config
+            .iter()
+            .filter_map(|(k, v)| Some((k.strip_prefix("keys.")?, v.as_str())))
+            .collect::<Vec<_>>();
+        settings.sort();
+
+        for (key, value) in settings {
+            match key {
+                "mode" => match value.to_lowercase().as_str() {
+                    "emacs" => key_config.edit_mode = EditMode::Emacs,
+                    "vi" => key_config.edit_mode = EditMode::Vi,
+                    _ => println!("Unknown edit mode: {}", value),
+                },
+                "completion" => match value.to_lowercase().as_str() {
+                    "circular" => key_config.completion_type = CompletionType::Circular,
+                    "list" => key_config.completion_type = CompletionType::List,
+                    _ => println!("Unknown completion type: {}", value),
+                },
+                _ => match (parse_keys(key), value.parse::<KeyAction>()) {
+                    (Ok(keys), Ok(action)) => {
+                        key_config.bindings.retain(|(bound, _)| *bound != keys);
+                        key_config.bindings.push((keys, action));
+                    }
+                    (Err(e), _) | (_, Err(e)) => println!("Ignore the key binding `{}`: {}", key, e),
+                },
+            }
+        }
+        key_config
+    }
+
+    pub fn config(&self) -> Config {
+        Config::builder()
+            .edit_mode(self.edit_mode)
+            .completion_type(self.completion_type)
+            .build()
+    }
+
+    /// bind the keys of `rl`, whose helper shares `line_buffer` with the key
+    /// handlers.
+    pub fn bind(&self, rl: &mut Editor<EditHelper>, editor: &ExternalEditor, line_buffer: &Arc<LineBuffer>) {
+        for (keys, action) in &self.bindings {
+            let handler = match action {
+                KeyAction::ExecuteStatement => EventHandler::Conditional(Box::new(StatementKeyHandler {
+                    line_buffer: Arc::clone(line_buffer),
+                })),
+                KeyAction::Newline => EventHandler::Simple(Cmd::Newline),
+                KeyAction::ToggleFormat => EventHandler::Conditional(Box::new(FormatKeyHandler)),
+                KeyAction::OpenEditor => {
+                    EventHandler::Conditional(Box::new(editor.key_handler(Arc::clone(line_buffer))))
+                }
+            };
+            rl.bind_sequence(Event::KeySeq(keys.clone().into()), handler);
+        }
+    }
+}
+
+/// submits the statement under the cursor, the rest of the input is put back
+/// at the next prompt.
+struct StatementKeyHandler {
+    line_buffer: Arc<LineBuffer>,
+}
+
+impl ConditionalEventHandler for StatementKeyHandler {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        let input = ctx.line();
+        let span = lexer::statement_at(input, ctx.pos());
+        let statement = input[span.clone()].trim();
+        if statement.is_empty() {
+            return Some(Cmd::Noop);
+        }
+        let rest = [input[..span.start].trim(), input[span.end..].trim()]
+            .iter()
+            .filter(|part| !part.is_empty())
+            .copied()
+            .collect::<Vec<_>>()
+            .join("\n");
+        *self.line_buffer.submitted.lock().unwrap() = Some((statement.to_string(), rest));
+        Some(Cmd::AcceptLine)
+    }
+}
+
+/// switches to the next output format and tells which one on its own line.
+struct FormatKeyHandler;
+
+impl ConditionalEventHandler for FormatKeyHandler {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        let format = toggle_output_format();
+        // the terminal is in raw mode, the line is drawn again below the message
+        print!("\r\n(output format: {})\r\n", format);
+        let _ = io::stdout().flush();
+        Some(Cmd::Replace(Movement::WholeBuffer, Some(ctx.line().to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_key_sequences() {
+        assert_eq!(parse_keys("ctrl-x ctrl-e"), Ok(vec![KeyEvent::ctrl('X'), KeyEvent::ctrl('E')]));
+        assert_eq!(parse_keys("alt-enter"), Ok(vec![KeyEvent(KeyCode::Enter, Modifiers::ALT)]));
+        assert_eq!(parse_keys("F2"), Ok(vec![KeyEvent(KeyCode::F(2), Modifiers::NONE)]));
+        assert!(parse_keys("ctrl-nope").is_err());
+        assert!(parse_keys("").is_err());
+    }
+}
@@ -0,0 +1,566 @@
+//! A tokenizer for Byzer-lang scripts. It never fails: every byte of the input
+//! ends up in exactly one token, and tokens cut off by the end of the input,
+//! such as an unclosed string, are marked as not terminated.
+
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Whitespace,
+    /// `-- ...` up to the end of the line
+    LineComment,
+    /// `/* ... */`
+    BlockComment,
+    /// identifiers and keywords
+    Word,
+    Number,
+    /// `'...'` or `"..."`
+    String,
+    /// `'''...'''`, which holds embedded python or sql verbatim
+    BlockString,
+    /// `` `...` ``, paths and quoted identifiers
+    Backtick,
+    /// `${...}`
+    Variable,
+    /// `!show`, `!if` and the other `!` commands
+    Command,
+    Semicolon,
+    /// any other symbol, such as `.`, `,`, `(`, `)` or `=`
+    Punct,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    /// byte offset of the token in the input
+    pub start: usize,
+    /// false if the input ended before the token was closed
+    pub terminated: bool,
+}
+
+impl<'a> Token<'a> {
+    pub fn end(&self) -> usize {
+        self.start + self.text.len()
+    }
+
+    /// whitespace and comments, which don't change the meaning of a script.
+    pub fn is_trivia(&self) -> bool {
+        matches!(
+            self.kind,
+            TokenKind::Whitespace | TokenKind::LineComment | TokenKind::BlockComment
+        )
+    }
+}
+
+struct Lexer<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        self.rest().chars().nth(1)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat_while<F: Fn(char) -> bool>(&mut self, f: F) {
+        while let Some(c) = self.peek() {
+            if !f(c) {
+                break;
+            }
+            self.bump();
+        }
+    }
+
+    /// consume up to and including `end`, returns false if the input ended first.
+    fn eat_until(&mut self, end: &str) -> bool {
+        match self.rest().find(end) {
+            Some(i) => {
+                self.pos += i + end.len();
+                true
+            }
+            None => {
+                self.pos = self.input.len();
+                false
+            }
+        }
+    }
+
+    /// consume a quoted string whose opening quote is already consumed,
+    /// backslash escapes the next character.
+    fn eat_quoted(&mut self, quote: char) -> bool {
+        while let Some(c) = self.bump() {
+            if c == '\\' {
+                self.bump();
+            } else if c == quote {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// consume a backtick quoted text, a doubled backtick stands for itself.
+    fn eat_backtick(&mut self) -> bool {
+        while let Some(c) = self.bump() {
+            if c == '`' {
+                if self.peek() == Some('`') {
+                    self.bump();
+                } else {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn next_token(&mut self) -> Option<Token<'a>> {
+        let start = self.pos;
+        let c = self.peek()?;
+        let mut terminated = true;
+
+        let kind = if c.is_whitespace() {
+            self.eat_while(char::is_whitespace);
+            TokenKind::Whitespace
+        } else if self.rest().starts_with("--") {
+            self.eat_while(|c| c != '\n');
+            TokenKind::LineComment
+        } else if self.rest().starts_with("/*") {
+            self.pos += 2;
+            terminated = self.eat_until("*/");
+            TokenKind::BlockComment
+        } else if self.rest().starts_with("'''") {
+            self.pos += 3;
+            terminated = self.eat_until("'''");
+            TokenKind::BlockString
+        } else if c == '\'' || c == '"' {
+            self.bump();
+            terminated = self.eat_quoted(c);
+            TokenKind::String
+        } else if c == '`' {
+            self.bump();
+            terminated = self.eat_backtick();
+            TokenKind::Backtick
+        } else if self.rest().starts_with("${") {
+            self.pos += 2;
+            terminated = self.eat_until("}");
+            TokenKind::Variable
+        } else if c == '!' && self.peek_second().is_some_and(is_word_char) {
+            self.bump();
+            self.eat_while(is_word_char);
+            TokenKind::Command
+        } else if c.is_ascii_digit() {
+            self.eat_while(|c| is_word_char(c) || c == '.');
+            TokenKind::Number
+        } else if is_word_char(c) {
+            self.eat_while(is_word_char);
+            TokenKind::Word
+        } else if c == ';' {
+            self.bump();
+            TokenKind::Semicolon
+        } else {
+            self.bump();
+            TokenKind::Punct
+        };
+
+        Some(Token {
+            kind,
+            text: &self.input[start..self.pos],
+            start,
+            terminated,
+        })
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// split `input` into tokens.
+pub fn tokenize(input: &str) -> Vec<Token<'_>> {
+    let mut lexer = Lexer { input, pos: 0 };
+    let mut tokens = vec![];
+    while let Some(token) = lexer.next_token() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// whether `script` is ready to be submitted: nothing is left open, and its
+/// last token other than whitespace and comments is a `;`.
+pub fn is_complete(script: &str) -> bool {
+    let tokens = tokenize(script);
+    if tokens.iter().any(|t| !t.terminated) {
+        return false;
+    }
+    matches!(
+        tokens.iter().rev().find(|t| !t.is_trivia()),
+        Some(Token {
+            kind: TokenKind::Semicolon,
+            ..
+        })
+    )
+}
+
+fn closing_bracket(open: &str) -> Option<&'static str> {
+    match open {
+        "(" => Some(")"),
+        "[" => Some("]"),
+        "{" => Some("}"),
+        _ => None,
+    }
+}
+
+fn is_closing_bracket(text: &str) -> bool {
+    matches!(text, ")" | "]" | "}")
+}
+
+/// pair the brackets of `tokens` by index, and return the first closing bracket
+/// which doesn't match, or else the innermost bracket left open.
+fn pair_brackets(tokens: &[Token]) -> (Vec<(usize, usize)>, Option<usize>) {
+    let mut pairs = vec![];
+    let mut open: Vec<usize> = vec![];
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind != TokenKind::Punct {
+            continue;
+        }
+        if closing_bracket(token.text).is_some() {
+            open.push(i);
+        } else if is_closing_bracket(token.text) {
+            match open.pop() {
+                Some(o) if closing_bracket(tokens[o].text) == Some(token.text) => pairs.push((o, i)),
+                _ => return (pairs, Some(i)),
+            }
+        }
+    }
+    (pairs, open.last().copied())
+}
+
+/// the quotes delimiting a string, block string or backtick token.
+fn quotes(token: &Token) -> Option<(Range<usize>, Range<usize>)> {
+    let len = match token.kind {
+        TokenKind::String | TokenKind::Backtick => 1,
+        TokenKind::BlockString => 3,
+        _ => return None,
+    };
+    if !token.terminated || token.text.len() < len * 2 {
+        return None;
+    }
+    Some((token.start..token.start + len, token.end() - len..token.end()))
+}
+
+/// the bracket or quote at `pos` in `input`, or else right before it, and the
+/// one it pairs with.
+pub fn matching_delimiter(input: &str, pos: usize) -> Option<(Range<usize>, Range<usize>)> {
+    let tokens = tokenize(input);
+    let (pairs, _) = pair_brackets(&tokens);
+    let at = |offset: usize| {
+        let i = tokens.iter().position(|t| t.start <= offset && offset < t.end())?;
+        let range = |i: usize| tokens[i].start..tokens[i].end();
+        if let Some(&(open, close)) = pairs.iter().find(|(open, close)| *open == i || *close == i) {
+            return Some(if open == i {
+                (range(open), range(close))
+            } else {
+                (range(close), range(open))
+            });
+        }
+        let (open, close) = quotes(&tokens[i])?;
+        if open.contains(&offset) {
+            Some((open, close))
+        } else if close.contains(&offset) {
+            Some((close, open))
+        } else {
+            None
+        }
+    };
+    at(pos).or_else(|| at(pos.checked_sub(1)?))
+}
+
+/// a bracket in `script` without its pair, outside of strings and comments.
+pub fn unbalanced_bracket(script: &str) -> Option<Token<'_>> {
+    let tokens = tokenize(script);
+    let (_, unbalanced) = pair_brackets(&tokens);
+    unbalanced.map(|i| tokens[i])
+}
+
+/// the string, backtick path or block comment of `script` which is not
+/// closed, if any.
+pub fn unterminated(script: &str) -> Option<Token<'_>> {
+    tokenize(script).into_iter().find(|t| !t.terminated)
+}
+
+/// the statement of `script` around `pos` with its `;`, statements being
+/// separated by the `;` outside strings and comments.
+pub fn statement_at(script: &str, pos: usize) -> Range<usize> {
+    let mut start = 0;
+    for token in tokenize(script) {
+        if token.kind != TokenKind::Semicolon {
+            continue;
+        }
+        if token.end() >= pos {
+            return start..token.end();
+        }
+        start = token.end();
+    }
+    start..script.len()
+}
+
+/// the statements of `script`, each with its `;` and without the whitespace
+/// around it. A `!if ... !fi;` block is kept as a single statement, since its
+/// branches only make sense together.
+pub fn split_statements(script: &str) -> Vec<&str> {
+    statement_spans(script).into_iter().map(|span| &script[span]).collect()
+}
+
+/// the spans of the statements `split_statements` returns.
+pub fn statement_spans(script: &str) -> Vec<Range<usize>> {
+    let trimmed = |range: Range<usize>| {
+        let text = &script[range.clone()];
+        let start = range.start + (text.len() - text.trim_start().len());
+        start..start + text.trim().len()
+    };
+    let mut statements = vec![];
+    let mut start = 0;
+    let mut depth = 0usize;
+    let mut has_code = false;
+    for token in tokenize(script) {
+        match token.kind {
+            TokenKind::Command if token.text.eq_ignore_ascii_case("!if") => depth += 1,
+            TokenKind::Command if token.text.eq_ignore_ascii_case("!fi") => depth = depth.saturating_sub(1),
+            TokenKind::Semicolon if depth == 0 => {
+                if has_code {
+                    statements.push(trimmed(start..token.end()));
+                }
+                start = token.end();
+                has_code = false;
+                continue;
+            }
+            _ => {}
+        }
+        has_code |= !token.is_trivia() && token.kind != TokenKind::Semicolon;
+    }
+    if has_code {
+        statements.push(trimmed(start..script.len()));
+    }
+    statements
+}
+
+/// the table a statement ending with `as <table>;` defines.
+pub fn output_table(statement: &str) -> Option<&str> {
+    let tokens = tokenize(statement);
+    let mut code = tokens
+        .iter()
+        .rev()
+        .filter(|t| !t.is_trivia())
+        .skip_while(|t| t.kind == TokenKind::Semicolon);
+    match (code.next(), code.next()) {
+        (Some(table), Some(as_)) if table.kind == TokenKind::Word && as_.text.eq_ignore_ascii_case("as") => {
+            Some(table.text)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(input: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(input)
+            .into_iter()
+            .filter(|t| t.kind != TokenKind::Whitespace)
+            .map(|t| (t.kind, t.text))
+            .collect()
+    }
+
+    #[test]
+    fn tokens_cover_the_input() {
+        let script = "load csv.`/tmp/a b.csv` where header=\"true\" as t; -- done\n/* x */select 1.5;";
+        let joined = tokenize(script).iter().map(|t| t.text).collect::<String>();
+        assert_eq!(joined, script);
+    }
+
+    #[test]
+    fn tokenize_statement() {
+        assert_eq!(
+            kinds("load csv.`/tmp/a;b` where header=\"t;\" as t;"),
+            vec![
+                (TokenKind::Word, "load"),
+                (TokenKind::Word, "csv"),
+                (TokenKind::Punct, "."),
+                (TokenKind::Backtick, "`/tmp/a;b`"),
+                (TokenKind::Word, "where"),
+                (TokenKind::Word, "header"),
+                (TokenKind::Punct, "="),
+                (TokenKind::String, "\"t;\""),
+                (TokenKind::Word, "as"),
+                (TokenKind::Word, "t"),
+                (TokenKind::Semicolon, ";"),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_commands_variables_and_numbers() {
+        assert_eq!(
+            kinds("!show jobs; select ${day}, 1.5, a != 2;"),
+            vec![
+                (TokenKind::Command, "!show"),
+                (TokenKind::Word, "jobs"),
+                (TokenKind::Semicolon, ";"),
+                (TokenKind::Word, "select"),
+                (TokenKind::Variable, "${day}"),
+                (TokenKind::Punct, ","),
+                (TokenKind::Number, "1.5"),
+                (TokenKind::Punct, ","),
+                (TokenKind::Word, "a"),
+                (TokenKind::Punct, "!"),
+                (TokenKind::Punct, "="),
+                (TokenKind::Number, "2"),
+                (TokenKind::Semicolon, ";"),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_escapes() {
+        assert_eq!(
+            kinds(r#"'it\'s;' "say \"hi;\"" `a``;b`"#),
+            vec![
+                (TokenKind::String, r#"'it\'s;'"#),
+                (TokenKind::String, r#""say \"hi;\"""#),
+                (TokenKind::Backtick, "`a``;b`"),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_block_string() {
+        let script = "run command as Ray.`` where code='''\nprint('a;b')\n''' as t;";
+        let block = tokenize(script)
+            .into_iter()
+            .find(|t| t.kind == TokenKind::BlockString)
+            .unwrap();
+        assert_eq!(block.text, "'''\nprint('a;b')\n'''");
+        assert!(block.terminated);
+    }
+
+    #[test]
+    fn unterminated_tokens() {
+        for script in ["select 'a;", "select \"a;", "load csv.`/tmp;", "/* a;", "code='''x;", "${a;"] {
+            let last = *tokenize(script).last().unwrap();
+            assert!(!last.terminated, "{}", script);
+        }
+    }
+
+    #[test]
+    fn complete_scripts() {
+        for script in [
+            "select 1 as t;",
+            "select 1 as t;   ",
+            "select 1 as t;\n",
+            "select 1 as t; -- trailing comment",
+            "select 1 as t; /* trailing\ncomment */",
+            "select ';' as a;",
+            "load csv.`/tmp/a;b` as t;",
+            "select 1 as t; -- '''",
+            "run command as Ray.`` where code='''\nx = 1;\n''' as t;",
+            "!show version;",
+        ] {
+            assert!(is_complete(script), "{:?}", script);
+        }
+    }
+
+    #[test]
+    fn incomplete_scripts() {
+        for script in [
+            "",
+            "   ",
+            "select 1 as t",
+            "select ';",
+            "select \"a;\" as b",
+            "load csv.`/tmp/a;",
+            "select 1 as t -- ;",
+            "select 1 /* ; */",
+            "select 1; /* unclosed comment",
+            "run command as Ray.`` where code='''\nx = 1;",
+            "run command as Ray.`` where code='''\nx = 1;\n''';\n'''",
+        ] {
+            assert!(!is_complete(script), "{:?}", script);
+        }
+    }
+
+    #[test]
+    fn match_brackets() {
+        let script = "select (a + (b)) as t where x=\"(\";";
+        assert_eq!(matching_delimiter(script, 7), Some((7..8, 15..16)));
+        assert_eq!(matching_delimiter(script, 16), Some((15..16, 7..8)));
+        assert_eq!(matching_delimiter(script, 12), Some((12..13, 14..15)));
+        assert_eq!(matching_delimiter(script, 2), None);
+    }
+
+    #[test]
+    fn match_quotes() {
+        let script = "load csv.`/tmp/a` where code='''x''';";
+        assert_eq!(matching_delimiter(script, 9), Some((9..10, 16..17)));
+        assert_eq!(matching_delimiter(script, 17), Some((16..17, 9..10)));
+        assert_eq!(matching_delimiter(script, 29), Some((29..32, 33..36)));
+        assert_eq!(matching_delimiter(script, 35), Some((33..36, 29..32)));
+        assert_eq!(matching_delimiter(script, 20), None);
+    }
+
+    #[test]
+    fn unbalanced_brackets() {
+        assert_eq!(unbalanced_bracket("select (a) as t;"), None);
+        assert_eq!(unbalanced_bracket("select \"(\" as t; -- )"), None);
+        assert_eq!(unbalanced_bracket("select ((a) as t;").map(|t| t.start), Some(7));
+        assert_eq!(unbalanced_bracket("select (a] as t;").map(|t| t.start), Some(9));
+        assert_eq!(unbalanced_bracket("select a) as t;").map(|t| t.start), Some(8));
+    }
+
+    #[test]
+    fn statement_around_cursor() {
+        let script = "select 1 as a;\nselect \";\" as b;\nselect 3";
+        assert_eq!(&script[statement_at(script, 3)], "select 1 as a;");
+        assert_eq!(&script[statement_at(script, 14)], "select 1 as a;");
+        assert_eq!(&script[statement_at(script, 20)], "\nselect \";\" as b;");
+        assert_eq!(&script[statement_at(script, script.len())], "\nselect 3");
+    }
+
+    #[test]
+    fn split_into_statements() {
+        let script = "set a=\"1;2\";\n-- load it\nload csv.`/tmp/a;b` as t;;\n\
+                      !if ''' 1 == 1 ''';\nselect 1 as x;\n!fi;\nselect * from t";
+        assert_eq!(
+            split_statements(script),
+            vec![
+                "set a=\"1;2\";",
+                "-- load it\nload csv.`/tmp/a;b` as t;",
+                "!if ''' 1 == 1 ''';\nselect 1 as x;\n!fi;",
+                "select * from t",
+            ]
+        );
+        assert!(split_statements(" -- nothing\n;").is_empty());
+    }
+
+    #[test]
+    fn table_defined_by_statement() {
+        assert_eq!(output_table("select 1 as t; -- done"), Some("t"));
+        assert_eq!(output_table("load csv.`/tmp/a` AS t1"), Some("t1"));
+        assert_eq!(output_table("save overwrite t as csv.`/tmp/a`;"), None);
+        assert_eq!(output_table("set a=\"b\";"), None);
+    }
+}
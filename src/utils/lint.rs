@@ -0,0 +1,423 @@
+//! Checks a script for mistakes which would otherwise only show up after a
+//! round trip to the engine.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+
+use colored::*;
+
+use crate::utils::conf::ByzerConf;
+use crate::utils::lexer::{self, TokenKind};
+use crate::utils::parser::{self, Source, Statement, StatementKind, Value};
+use crate::utils::vars;
+
+/// the data sources the engine ships with, compared case-insensitively.
+const FORMATS: [&str; 37] = [
+    "csv",
+    "json",
+    "parquet",
+    "orc",
+    "text",
+    "xml",
+    "excel",
+    "delta",
+    "hive",
+    "jdbc",
+    "es",
+    "solr",
+    "hbase",
+    "mongo",
+    "redis",
+    "kafka",
+    "adHocKafka",
+    "mockStream",
+    "image",
+    "binaryFile",
+    "csvStr",
+    "jsonStr",
+    "script",
+    "modelList",
+    "modelParams",
+    "modelExample",
+    "modelExplain",
+    "mlsqlAPI",
+    "mlsqlConf",
+    "rest",
+    "crawlersql",
+    "fs",
+    "console",
+    "webConsole",
+    "streamParquet",
+    "newParquet",
+    "_mlsql_",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    pub severity: Severity,
+    /// such as `undefined-table`
+    pub rule: &'static str,
+    pub message: String,
+    pub span: Range<usize>,
+}
+
+impl Lint {
+    fn new(severity: Severity, rule: &'static str, message: String, span: Range<usize>) -> Self {
+        Self {
+            severity,
+            rule,
+            message,
+            span,
+        }
+    }
+
+    /// render the lint with the line of `script` it is about, `origin` being
+    /// the file the script was read from if any.
+    pub fn render(&self, script: &str, origin: Option<&str>) -> String {
+        let (line_num, column) = line_and_column(script, self.span.start);
+        let line = script.lines().nth(line_num - 1).unwrap_or_default();
+        let gutter = line_num.to_string();
+        let padding = " ".repeat(gutter.len());
+        let severity = match self.severity {
+            Severity::Error => self.severity.to_string().red().bold(),
+            Severity::Warning => self.severity.to_string().yellow().bold(),
+        };
+
+        let mut out = format!("{}[{}]: {}\n", severity, self.rule, self.message.bold());
+        let location = match origin {
+            Some(origin) => format!("{}:{}:{}", origin, line_num, column),
+            None => format!("line {}, column {}", line_num, column),
+        };
+        out.push_str(&format!("{}{} {}\n", padding, "-->".blue(), location));
+        out.push_str(&format!("{} {} {}\n", gutter.blue(), "|".blue(), line));
+        // underline up to the end of the line when the span goes further
+        let line_start = script[..self.span.start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let underline_len = script[self.span.start..self.span.end.max(self.span.start)]
+            .split('\n')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .count()
+            .max(1);
+        let offset = script[line_start..self.span.start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        out.push_str(&format!(
+            "{} {} {}{}\n",
+            padding,
+            "|".blue(),
+            offset,
+            "^".repeat(underline_len).red().bold()
+        ));
+        out
+    }
+}
+
+/// one based line and column of the byte offset `pos` in `script`.
+pub fn line_and_column(script: &str, pos: usize) -> (usize, usize) {
+    let before = &script[..pos.min(script.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// With `shell.lint.block=true` a script with lint errors is not executed.
+/// `shell.lint.formats` adds comma separated data sources to the known ones.
+#[derive(Debug, Clone, Default)]
+pub struct Linter {
+    pub block: bool,
+    formats: Vec<String>,
+}
+
+impl Linter {
+    pub fn new(byzer_conf: &ByzerConf) -> Self {
+        let formats: String = byzer_conf.shell_config_or("lint.formats", String::new());
+        Self {
+            block: byzer_conf.shell_config_or("lint.block", false),
+            formats: formats
+                .split(',')
+                .map(|f| f.trim().to_lowercase())
+                .filter(|f| !f.is_empty())
+                .collect(),
+        }
+    }
+
+    /// lint `script`, `known_tables` being the tables defined before it, such
+    /// as by the statements executed earlier in the session. Sorted by position.
+    pub fn lint(&self, script: &str, known_tables: &[String]) -> Vec<Lint> {
+        let parsed = parser::parse(script);
+        let mut lints = parsed
+            .errors
+            .iter()
+            .map(|e| Lint::new(Severity::Error, "syntax", e.message.clone(), e.span.clone()))
+            .collect::<Vec<_>>();
+
+        let mut context = Context {
+            script,
+            // `run command as ...` runs an ET on the empty table `command`
+            tables: known_tables
+                .iter()
+                .map(|t| t.to_lowercase())
+                .chain([String::from("command")])
+                .collect(),
+            formats: self.formats.iter().cloned().collect(),
+            // an included script may define any table
+            check_tables: !has_include(&parsed.statements),
+            lints: &mut lints,
+        };
+        context.statements(&parsed.statements);
+        lints.sort_by_key(|lint| lint.span.start);
+        lints
+    }
+}
+
+fn has_include(statements: &[Statement]) -> bool {
+    statements.iter().any(|statement| match &statement.kind {
+        StatementKind::Include { .. } => true,
+        StatementKind::If { branches, .. } => branches.iter().any(|b| has_include(&b.body)),
+        _ => false,
+    })
+}
+
+struct Context<'a> {
+    script: &'a str,
+    /// lowercase, as are the temp tables of the engine
+    tables: HashSet<String>,
+    /// the lowercase formats defined by `connect` or `shell.lint.formats`
+    formats: HashSet<String>,
+    check_tables: bool,
+    lints: &'a mut Vec<Lint>,
+}
+
+impl<'a> Context<'a> {
+    fn statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Load { source, table, .. } => {
+                self.format(source);
+                self.define(&table.node);
+            }
+            StatementKind::Select { sql, table } => {
+                self.select(&sql.node, sql.span.start);
+                self.define(&table.node);
+            }
+            StatementKind::Save { table, target, .. } => {
+                self.table(&table.node, table.span.clone());
+                self.format(target);
+                if matches!(&target.path.node, Value::Quoted(p) | Value::String(p) if p.trim().is_empty()) {
+                    self.lints.push(Lint::new(
+                        Severity::Error,
+                        "save-without-path",
+                        format!("`save` to `{}` needs a path or a table name", target.format.node),
+                        target.path.span.clone(),
+                    ));
+                }
+            }
+            StatementKind::Connect { format, alias, .. } => {
+                self.formats.insert(format.node.to_lowercase());
+                if let Some(alias) = alias {
+                    self.formats.insert(alias.node.to_lowercase());
+                }
+            }
+            StatementKind::Set { key, options, .. } => {
+                let is_conf = options.iter().any(|o| {
+                    o.key.node.eq_ignore_ascii_case("type")
+                        && matches!(&o.value.node, Value::String(t) if t.eq_ignore_ascii_case("conf"))
+                });
+                if !is_conf && !is_used(&self.script[statement.span.end..], &key.node) {
+                    self.lints.push(Lint::new(
+                        Severity::Warning,
+                        "unused-variable",
+                        format!("`{}` is never used", key.node),
+                        key.span.clone(),
+                    ));
+                }
+            }
+            StatementKind::Train { table, output, .. } => {
+                self.table(&table.node, table.span.clone());
+                if let Some(output) = output {
+                    self.define(&output.node);
+                }
+            }
+            StatementKind::If { branches, .. } => {
+                for branch in branches {
+                    self.statements(&branch.body);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn define(&mut self, table: &str) {
+        self.tables.insert(table.to_lowercase());
+    }
+
+    /// check a table name used by a statement, `db.table` being a hive table.
+    fn table(&mut self, table: &str, span: Range<usize>) {
+        if !self.check_tables || table.starts_with("${") || self.tables.contains(&table.to_lowercase()) {
+            return;
+        }
+        self.lints.push(Lint::new(
+            Severity::Warning,
+            "undefined-table",
+            format!("table `{}` is not defined", table),
+            span,
+        ));
+    }
+
+    /// check the tables after `from` and `join`, other than those of a `with`.
+    fn select(&mut self, sql: &str, offset: usize) {
+        let tokens = lexer::tokenize(sql)
+            .into_iter()
+            .filter(|t| !t.is_trivia())
+            .collect::<Vec<_>>();
+        let is_word = |i: usize, word: &str| {
+            tokens
+                .get(i)
+                .is_some_and(|t| t.kind == TokenKind::Word && t.text.eq_ignore_ascii_case(word))
+        };
+        let is_punct = |i: usize, punct: &str| tokens.get(i).is_some_and(|t| t.kind == TokenKind::Punct && t.text == punct);
+
+        // `with name as (...)`
+        let ctes = (0..tokens.len())
+            .filter(|&i| is_word(i + 1, "as") && is_punct(i + 2, "("))
+            .map(|i| lexer_name(tokens[i].text).to_lowercase())
+            .collect::<HashSet<_>>();
+
+        for i in 0..tokens.len() {
+            if !(is_word(i, "from") || is_word(i, "join")) {
+                continue;
+            }
+            let token = match tokens.get(i + 1) {
+                Some(token) if matches!(token.kind, TokenKind::Word | TokenKind::Backtick) => token,
+                _ => continue,
+            };
+            // `db.table` and table valued functions such as `range(10)`
+            if is_punct(i + 2, ".") || is_punct(i + 2, "(") {
+                continue;
+            }
+            let table = lexer_name(token.text);
+            if !ctes.contains(&table.to_lowercase()) {
+                self.table(&table, offset + token.start..offset + token.end());
+            }
+        }
+    }
+
+    fn format(&mut self, source: &Source) {
+        let format = &source.format.node;
+        let lowercase = format.to_lowercase();
+        if format.starts_with("${")
+            || self.formats.contains(&lowercase)
+            || FORMATS.iter().any(|f| f.eq_ignore_ascii_case(format))
+        {
+            return;
+        }
+        self.lints.push(Lint::new(
+            Severity::Warning,
+            "unknown-format",
+            format!("unknown data source `{}`", format),
+            source.format.span.clone(),
+        ));
+    }
+}
+
+/// a table name without its backticks.
+fn lexer_name(text: &str) -> String {
+    match text.strip_prefix('`').and_then(|t| t.strip_suffix('`')) {
+        Some(name) => name.replace("``", "`"),
+        None => text.to_string(),
+    }
+}
+
+/// whether `rest` refers to the variable `key`, the same way as the local
+/// variables are looked for.
+fn is_used(rest: &str, key: &str) -> bool {
+    vars::references(rest).iter().any(|reference| reference.key == key)
+}
+
+/// print `lints` and tell whether there is an error among them.
+pub fn print_lints(lints: &[Lint], script: &str, origin: Option<&str>) -> bool {
+    for lint in lints {
+        println!("{}", lint.render(script, origin));
+    }
+    lints.iter().any(|lint| lint.severity == Severity::Error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(script: &str) -> Vec<(Severity, &'static str, String)> {
+        Linter::default()
+            .lint(script, &[String::from("session_table")])
+            .into_iter()
+            .map(|lint| (lint.severity, lint.rule, script[lint.span].to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn report_mistakes() {
+        let script = "load csv.`/tmp/a.csv` as a;\n\
+                      load csvv.`/tmp/b.csv` as b;\n\
+                      select * from a join missing on a.id = missing.id as c;\n\
+                      select 1 from b\n;\n\
+                      set day = \"2021-01-01\";\n\
+                      save overwrite c as parquet.``;\n\
+                      save append nowhere as parquet.`/tmp/x`;";
+        assert_eq!(
+            rules(script),
+            vec![
+                (Severity::Warning, "unknown-format", String::from("csvv")),
+                (Severity::Warning, "undefined-table", String::from("missing")),
+                (Severity::Error, "syntax", String::from(";")),
+                (Severity::Warning, "unused-variable", String::from("day")),
+                (Severity::Error, "save-without-path", String::from("``")),
+                (Severity::Warning, "undefined-table", String::from("nowhere")),
+            ]
+        );
+    }
+
+    #[test]
+    fn accept_valid_scripts() {
+        let script = "connect jdbc where url=\"jdbc:mysql://h/db\" as db_1;\n\
+                      load db_1.`users` as users;\n\
+                      set day = \"2021-01-01\";\n\
+                      set spark.sql.shuffle.partitions = \"8\" where type=\"conf\";\n\
+                      select * from (with u as (select * from users) select * from u), hive_db.t where day = \"${day}\" as t;\n\
+                      select * from session_table join `t` as joined;\n\
+                      select * from range(10) as numbers;\n\
+                      train joined as RandomForest.`/tmp/model` as model_table;\n\
+                      run command as ShowTablesExt.`` as tables;\n\
+                      save overwrite model_table as delta.`db.model`;";
+        assert_eq!(rules(script), vec![]);
+    }
+
+    #[test]
+    fn position_of_offset() {
+        assert_eq!(line_and_column("ab\ncd", 0), (1, 1));
+        assert_eq!(line_and_column("ab\ncd", 4), (2, 2));
+        assert_eq!(line_and_column("ab\ncd", 5), (2, 3));
+    }
+}
@@ -0,0 +1,307 @@
+use std::io::BufReader;
+use std::sync::{Arc, Mutex};
+
+use reqwest::blocking::Response;
+use serde_json::Value;
+
+use crate::utils::completion::SessionState;
+use crate::utils::conf::ByzerConf;
+use crate::utils::diagnostic::print_error;
+use crate::utils::engine::EngineClient;
+use crate::utils::include::SourceMap;
+use crate::utils::interrupt::InterruptListener;
+use crate::utils::parser::{self, StatementKind};
+use crate::utils::progress_bar::ExecutingState;
+use crate::utils::reader::StreamingValueReader;
+use crate::utils::{execute_script, print_as_table, print_value, run_cancellable};
+
+/// the last query whose result is being paged through.
+struct PagedQuery {
+    script: String,
+    /// number of rows printed so far
+    shown: usize,
+    /// whether the engine may hold more rows than fetched
+    has_more: bool,
+    /// whether the script can be run again for more rows without side effects
+    rerunnable: bool,
+    /// where the lines of the script come from, if it has inlined includes
+    source_map: Option<SourceMap>,
+}
+
+/// whether running `sql` again only reads, that is it has no statements but
+/// `select` and plain `set`, or `:more` could repeat its side effects.
+fn is_read_only(sql: &str) -> bool {
+    let script = parser::parse(sql);
+    script.errors.is_empty()
+        && script.statements.iter().all(|statement| match &statement.kind {
+            StatementKind::Select { .. } => true,
+            StatementKind::Set { options, .. } => options.is_empty(),
+            _ => false,
+        })
+}
+
+/// Prints results one page at a time. Every query asks the engine for one row
+/// more than the page size, to tell whether the result was cut. `:more` runs
+/// the last query again with a larger `outputSize` and prints the next page,
+/// provided the query only reads.
+///
+/// With `shell.output.stream=true` the result is instead read while the engine
+/// sends it, and printed in pages of `shell.output.size` rows as they arrive,
+/// up to `shell.output.stream.max.rows` rows.
+pub struct ResultPager {
+    engine: Arc<EngineClient>,
+    page_size: usize,
+    streaming: bool,
+    max_streamed_rows: usize,
+    last: Option<PagedQuery>,
+    session: Arc<Mutex<SessionState>>,
+}
+
+impl ResultPager {
+    /// the page size is read from `shell.output.size`, 50 rows by default.
+    /// Successful queries are recorded in `session`.
+    pub fn new(engine: Arc<EngineClient>, byzer_conf: &ByzerConf, session: Arc<Mutex<SessionState>>) -> Self {
+        Self {
+            engine,
+            page_size: byzer_conf.shell_config_or("output.size", 50usize).max(1),
+            streaming: byzer_conf.shell_config_or("output.stream", false),
+            max_streamed_rows: byzer_conf.shell_config_or("output.stream.max.rows", 10000usize),
+            last: None,
+            session,
+        }
+    }
+
+    /// the tables defined by the queries executed so far.
+    pub fn session_tables(&self) -> Vec<String> {
+        self.session.lock().unwrap().tables().to_vec()
+    }
+
+    /// execute `sql` and print the first page of its result, `source_map`
+    /// tells where its lines come from for its errors.
+    pub fn run(&mut self, sql: &str, source_map: Option<&SourceMap>) -> ExecutingState {
+        self.last = None;
+        if self.streaming {
+            return self.run_streaming(sql, source_map);
+        }
+        match self.fetch(sql, self.page_size + 1, source_map) {
+            Ok(Some(rows)) => {
+                self.session.lock().unwrap().record(sql, rows.first());
+                let mut query = PagedQuery {
+                    script: sql.to_string(),
+                    shown: 0,
+                    has_more: false,
+                    rerunnable: is_read_only(sql),
+                    source_map: source_map.cloned(),
+                };
+                self.print_page(&mut query, rows);
+                self.last = Some(query);
+                ExecutingState::Succeeded
+            }
+            Ok(None) => ExecutingState::Succeeded,
+            Err(state) => state,
+        }
+    }
+
+    /// execute `sql` without printing its result, only its errors. The first
+    /// row is still fetched for the columns of the table it defines.
+    pub fn run_without_result(&mut self, sql: &str, source_map: Option<&SourceMap>) -> ExecutingState {
+        self.last = None;
+        let extra_params = vec![("outputSize".to_string(), String::from("1"))];
+        match execute_script(&self.engine, sql, extra_params) {
+            Some((200, res)) => {
+                let rows = serde_json::from_str::<Value>(res.as_str()).ok();
+                let first_row = rows.as_ref().and_then(|rows| rows.as_array()?.first());
+                self.session.lock().unwrap().record(sql, first_row);
+                ExecutingState::Succeeded
+            }
+            Some((_, res)) => {
+                print_error(sql, res.as_str(), source_map);
+                ExecutingState::Failed
+            }
+            None => ExecutingState::Cancelled,
+        }
+    }
+
+    /// print the next page of the last query, for `:more`.
+    pub fn more(&mut self) {
+        match self.last.take() {
+            Some(query) if query.has_more && !query.rerunnable => {
+                println!(
+                    "The last script is not only `select` statements, running it again could repeat its side effects."
+                );
+                self.last = Some(query);
+            }
+            Some(mut query) if query.has_more => {
+                let output_size = query.shown + self.page_size + 1;
+                if let Ok(Some(rows)) = self.fetch(query.script.as_str(), output_size, query.source_map.as_ref()) {
+                    self.print_page(&mut query, rows);
+                    self.last = Some(query);
+                }
+            }
+            Some(query) => {
+                println!("No more rows.");
+                self.last = Some(query);
+            }
+            None => println!("No result to page through."),
+        }
+    }
+
+    /// execute `sql` and print its rows while they are received, only the page
+    /// being printed is kept in memory.
+    fn run_streaming(&self, sql: &str, source_map: Option<&SourceMap>) -> ExecutingState {
+        let listener = InterruptListener::new();
+        let job_name = self.engine.new_job_name();
+        let output_size = (self.max_streamed_rows + 1).to_string();
+        let script = sql.to_string();
+        let resp = run_cancellable(
+            &self.engine,
+            job_name.as_str(),
+            &listener,
+            move |engine, job_name| {
+                engine.stream_job(script.as_str(), job_name, &[("outputSize", output_size.as_str())])
+            },
+            |resp| matches!(resp, Ok(r) if r.status().is_success()),
+        );
+
+        let resp = match resp {
+            Some(Ok(resp)) if resp.status().is_success() => resp,
+            Some(Ok(resp)) => {
+                print_error(sql, resp.text().unwrap_or_default().as_str(), source_map);
+                return ExecutingState::Failed;
+            }
+            Some(Err(e)) => {
+                print_error(sql, e.as_str(), source_map);
+                return ExecutingState::Failed;
+            }
+            None => return ExecutingState::Cancelled,
+        };
+
+        let (streamed, interrupted) = self.print_stream(sql, resp, &listener);
+        if interrupted {
+            self.engine.kill_job(job_name.as_str());
+            println!("🚫 cancelled after {} rows.", streamed);
+            return ExecutingState::Cancelled;
+        } else if streamed > self.max_streamed_rows {
+            println!(
+                "stopped after {} rows, raise `shell.output.stream.max.rows` to see more.",
+                self.max_streamed_rows
+            );
+        } else if streamed > self.page_size {
+            println!("{} rows.", streamed);
+        }
+        ExecutingState::Succeeded
+    }
+
+    /// print the rows of `resp` page by page, returns how many rows were read
+    /// and whether Ctrl-C was pressed. Stops early on Ctrl-C, which is checked
+    /// after every page, or once more than `max_streamed_rows` rows are read.
+    fn print_stream(&self, sql: &str, resp: Response, listener: &InterruptListener) -> (usize, bool) {
+        let rows = StreamingValueReader::new(BufReader::new(resp)).rows();
+        let mut page = Vec::with_capacity(self.page_size);
+        let mut count = 0;
+        let mut printed = false;
+        for row in rows {
+            match row {
+                Ok(value) => {
+                    if count == 0 {
+                        self.session.lock().unwrap().record(sql, Some(&value));
+                    }
+                    count += 1;
+                    if count > self.max_streamed_rows {
+                        break;
+                    }
+                    page.push(value);
+                }
+                Err(err) => eprintln!("error parsing row: {}", err),
+            }
+            if page.len() == self.page_size {
+                print_value(&Value::Array(std::mem::take(&mut page)));
+                printed = true;
+                if listener.interrupted() {
+                    return (count, true);
+                }
+            }
+        }
+        if count == 0 {
+            self.session.lock().unwrap().record(sql, None);
+        }
+        if !page.is_empty() || !printed {
+            print_value(&Value::Array(page));
+        }
+        (count, false)
+    }
+
+    /// run the script asking for `output_size` rows, errors and results that
+    /// are not tables are printed right away. Fails with how the script ended
+    /// if it did not succeed.
+    fn fetch(
+        &self,
+        sql: &str,
+        output_size: usize,
+        source_map: Option<&SourceMap>,
+    ) -> Result<Option<Vec<Value>>, ExecutingState> {
+        let extra_params = vec![("outputSize".to_string(), output_size.to_string())];
+        let (status, res) = execute_script(&self.engine, sql, extra_params).ok_or(ExecutingState::Cancelled)?;
+        if status != 200 {
+            print_error(sql, res.as_str(), source_map);
+            return Err(ExecutingState::Failed);
+        }
+        if let Ok(Value::Array(rows)) = serde_json::from_str::<Value>(res.as_str()) {
+            return Ok(Some(rows));
+        }
+        print_as_table(res.as_str());
+        Ok(None)
+    }
+
+    /// print the rows after the ones already shown, followed by a footer telling
+    /// which rows these are.
+    fn print_page(&self, query: &mut PagedQuery, rows: Vec<Value>) {
+        let total = rows.len();
+        let has_more = total > query.shown + self.page_size;
+        let page = rows
+            .into_iter()
+            .skip(query.shown)
+            .take(self.page_size)
+            .collect::<Vec<_>>();
+        let count = page.len();
+
+        if count == 0 && query.shown > 0 {
+            println!("No more rows.");
+            query.has_more = false;
+            return;
+        }
+        print_value(&Value::Array(page));
+
+        let (first, last) = (query.shown + 1, query.shown + count);
+        if has_more {
+            let next = if query.rerunnable {
+                "`:more` to fetch more"
+            } else {
+                "raise `shell.output.size` to see more"
+            };
+            if query.shown == 0 {
+                println!("showing {} of {}+ rows, {}.", last, total, next);
+            } else {
+                println!("showing {}-{} of {}+ rows, {}.", first, last, total, next);
+            }
+        } else if query.shown > 0 {
+            println!("showing {}-{} of {} rows.", first, last, last);
+        }
+
+        query.shown += count;
+        query.has_more = has_more;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_rerun_reads() {
+        assert!(is_read_only("set day=\"2021-01-01\";\nselect * from t where day=\"${day}\" as output;"));
+        assert!(!is_read_only("select 1 as a;\nsave overwrite a as parquet.`/tmp/a`;"));
+        assert!(!is_read_only("set x=`date` where type=\"shell\";\nselect \"${x}\" as a;"));
+        assert!(!is_read_only("!delta history /tmp/a;"));
+    }
+}
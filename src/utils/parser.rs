@@ -0,0 +1,951 @@
+//! A parser for Byzer-lang scripts. It builds on the tokens of the lexer and
+//! never gives up: a statement it can't make sense of is recorded as an error
+//! and skipped up to its `;`, so the rest of the script is still parsed.
+
+use std::ops::Range;
+
+use crate::utils::lexer::{self, Token, TokenKind};
+
+/// A node with the byte range of the script it was parsed from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Range<usize>,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Range<usize>) -> Self {
+        Self { node, span }
+    }
+}
+
+pub type Name = Spanned<String>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// `'...'` or `"..."` without its quotes
+    String(String),
+    /// `'''...'''` without its quotes
+    BlockString(String),
+    Number(String),
+    /// a bare word, such as `true`
+    Word(String),
+    /// `` `...` `` without its backticks
+    Quoted(String),
+    /// `${...}` as written
+    Variable(String),
+}
+
+/// `key=value` in a `where` or `options` clause.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionPair {
+    pub key: Name,
+    pub value: Spanned<Value>,
+}
+
+/// `format.path`, such as ``csv.`/tmp/a.csv` `` or `RandomForest.`/tmp/model``.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub format: Name,
+    pub path: Spanned<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveMode {
+    Overwrite,
+    Append,
+    ErrorIfExists,
+    Ignore,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrainKind {
+    Train,
+    Run,
+    Predict,
+}
+
+/// One branch of an `!if` block, the `!else` branch has no condition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IfBranch {
+    pub condition: Option<Spanned<Value>>,
+    /// from the `!if`, `!elif` or `!else` of the branch to its `;`
+    pub span: Range<usize>,
+    pub body: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatementKind {
+    /// `load <format>.<path> [where ...] as <table>;`
+    Load {
+        source: Source,
+        options: Vec<OptionPair>,
+        table: Name,
+    },
+    /// `select ... as <table>;`, `sql` being the text between `select` and `as`
+    Select { sql: Spanned<String>, table: Name },
+    /// `save [mode] <table> as <format>.<path> [where ...] [partitionBy ...] [coalesce n];`
+    Save {
+        mode: Option<Spanned<SaveMode>>,
+        table: Name,
+        target: Source,
+        options: Vec<OptionPair>,
+        partition_by: Vec<Name>,
+        coalesce: Option<Spanned<String>>,
+    },
+    /// `connect <format> [where ...] [as <name>];`
+    Connect {
+        format: Name,
+        options: Vec<OptionPair>,
+        alias: Option<Name>,
+    },
+    /// `set <key>=<value> [where ...];`
+    Set {
+        key: Name,
+        value: Spanned<Value>,
+        options: Vec<OptionPair>,
+    },
+    /// `train|run|predict <table> as <et>.<path> [where ...] [as <table>];`
+    Train {
+        kind: TrainKind,
+        table: Name,
+        model: Source,
+        options: Vec<OptionPair>,
+        output: Option<Name>,
+    },
+    /// `register <format>.<path> as <function> [where ...];`
+    Register {
+        source: Source,
+        function: Name,
+        options: Vec<OptionPair>,
+    },
+    /// `include <format>.<path> [where ...];`
+    Include { source: Source, options: Vec<OptionPair> },
+    /// `insert`, `create`, `drop`, `refresh` and the other statements passed
+    /// through to spark as is
+    Sql { keyword: Name },
+    /// `!show jobs;` and the other `!` commands, the name without its `!`
+    Command { name: Name, args: Vec<Spanned<String>> },
+    /// `!if ...; ... !elif ...; ... !else; ... !fi;`, `fi` being the span of
+    /// the `!fi`, which is missing when the block isn't closed
+    If {
+        branches: Vec<IfBranch>,
+        fi: Option<Range<usize>>,
+    },
+    /// a statement which could not be parsed, see the errors of the script
+    Invalid,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub kind: StatementKind,
+    /// from the first token of the statement to its `;`
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Script {
+    pub statements: Vec<Statement>,
+    pub errors: Vec<ParseError>,
+}
+
+/// the statements passed through to spark without being looked into.
+const SQL_KEYWORDS: [&str; 6] = ["insert", "create", "drop", "refresh", "truncate", "explain"];
+
+/// parse `script`, the errors are collected rather than returned early.
+pub fn parse(script: &str) -> Script {
+    let tokens = lexer::tokenize(script)
+        .into_iter()
+        .filter(|t| !t.is_trivia())
+        .collect::<Vec<_>>();
+    let mut parser = Parser {
+        script,
+        tokens,
+        pos: 0,
+        errors: vec![],
+    };
+    let statements = parser.statements(false);
+    Script {
+        statements,
+        errors: parser.errors,
+    }
+}
+
+struct Parser<'a> {
+    script: &'a str,
+    /// the tokens of the script other than whitespace and comments
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    errors: Vec<ParseError>,
+}
+
+type ParseResult<T> = Result<T, ParseError>;
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.pos)
+    }
+
+    fn bump(&mut self) -> Option<Token<'a>> {
+        let token = self.tokens.get(self.pos).copied();
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn at_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(t) if t.kind == TokenKind::Word && t.text.eq_ignore_ascii_case(word))
+    }
+
+    fn at_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Some(t) if t.kind == TokenKind::Punct && t.text == punct)
+    }
+
+    fn at_end_of_statement(&self) -> bool {
+        matches!(
+            self.peek(),
+            None | Some(Token {
+                kind: TokenKind::Semicolon,
+                ..
+            })
+        )
+    }
+
+    /// where the next token starts, or the end of the script.
+    fn here(&self) -> Range<usize> {
+        match self.peek() {
+            Some(token) => token.start..token.end(),
+            None => self.script.len()..self.script.len(),
+        }
+    }
+
+    fn error<T>(&self, message: String) -> ParseResult<T> {
+        let found = match self.peek() {
+            Some(token) => format!("`{}`", token.text),
+            None => String::from("the end of the script"),
+        };
+        Err(ParseError {
+            message: format!("{}, found {}", message, found),
+            span: self.here(),
+        })
+    }
+
+    fn expect_word(&mut self, word: &str) -> ParseResult<Token<'a>> {
+        if self.at_word(word) {
+            Ok(self.bump().unwrap())
+        } else {
+            self.error(format!("expected `{}`", word))
+        }
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> ParseResult<Token<'a>> {
+        if self.at_punct(punct) {
+            Ok(self.bump().unwrap())
+        } else {
+            self.error(format!("expected `{}`", punct))
+        }
+    }
+
+    /// parse statements up to the end of the script, or inside an `!if` block
+    /// up to its next `!elif`, `!else` or `!fi`.
+    fn statements(&mut self, in_if: bool) -> Vec<Statement> {
+        let mut statements = vec![];
+        while let Some(token) = self.peek() {
+            if token.kind == TokenKind::Semicolon {
+                self.bump();
+                continue;
+            }
+            if token.kind == TokenKind::Command && is_branch_command(token.text) {
+                if in_if {
+                    break;
+                }
+                self.errors.push(ParseError {
+                    message: format!("`{}` without `!if`", token.text),
+                    span: token.start..token.end(),
+                });
+                self.skip_statement();
+                continue;
+            }
+            statements.push(self.statement());
+        }
+        statements
+    }
+
+    fn statement(&mut self) -> Statement {
+        let start = self.pos;
+        let span_start = self.here().start;
+        let kind = match self.statement_kind() {
+            Ok(kind) => match self.end_of_statement() {
+                Ok(()) => kind,
+                Err(e) => {
+                    self.errors.push(e);
+                    self.skip_statement();
+                    StatementKind::Invalid
+                }
+            },
+            Err(e) => {
+                self.errors.push(e);
+                self.skip_statement();
+                StatementKind::Invalid
+            }
+        };
+        let span_end = self.tokens[start..self.pos]
+            .last()
+            .map(|t| t.end())
+            .unwrap_or(span_start);
+        Statement {
+            kind,
+            span: span_start..span_end,
+        }
+    }
+
+    /// consume the `;` of a statement, which may be missing at the end of the script.
+    fn end_of_statement(&mut self) -> ParseResult<()> {
+        match self.peek() {
+            None => Ok(()),
+            Some(token) if token.kind == TokenKind::Semicolon => {
+                self.bump();
+                Ok(())
+            }
+            Some(_) => self.error(String::from("expected `;`")),
+        }
+    }
+
+    /// skip to the end of the statement, its `;` included.
+    fn skip_statement(&mut self) {
+        while let Some(token) = self.bump() {
+            if token.kind == TokenKind::Semicolon {
+                break;
+            }
+        }
+    }
+
+    fn statement_kind(&mut self) -> ParseResult<StatementKind> {
+        let token = *self.peek().expect("statement_kind is called before the end");
+        if token.kind == TokenKind::Command {
+            return self.command();
+        }
+        if token.kind != TokenKind::Word {
+            return self.error(String::from("expected a statement"));
+        }
+        match token.text.to_lowercase().as_str() {
+            "load" => self.load(),
+            "select" => self.select(),
+            "save" => self.save(),
+            "connect" => self.connect(),
+            "set" => self.set(),
+            "train" => self.train(TrainKind::Train),
+            "run" => self.train(TrainKind::Run),
+            "predict" => self.train(TrainKind::Predict),
+            "register" => self.register(),
+            "include" => self.include(),
+            keyword if SQL_KEYWORDS.contains(&keyword) => {
+                self.bump();
+                while !self.at_end_of_statement() {
+                    self.bump();
+                }
+                Ok(StatementKind::Sql {
+                    keyword: Name::new(keyword.to_string(), token.start..token.end()),
+                })
+            }
+            _ => self.error(String::from("expected a statement")),
+        }
+    }
+
+    fn load(&mut self) -> ParseResult<StatementKind> {
+        self.expect_word("load")?;
+        let source = self.source()?;
+        let options = self.options()?;
+        if !self.at_word("as") {
+            return self.error(String::from("expected `as <table>` at the end of `load`"));
+        }
+        self.bump();
+        let table = self.name("a table name")?;
+        Ok(StatementKind::Load { source, options, table })
+    }
+
+    /// the sql is everything up to the last `as` of the statement.
+    fn select(&mut self) -> ParseResult<StatementKind> {
+        let select = self.expect_word("select")?;
+        let mut last_as = None;
+        while !self.at_end_of_statement() {
+            if self.at_word("as") {
+                last_as = Some(self.pos);
+            }
+            self.bump();
+        }
+        let as_pos = match last_as {
+            Some(i) if i + 2 == self.pos => i,
+            _ => return self.error(String::from("expected `as <table>` at the end of `select`")),
+        };
+        let sql_end = self.tokens[as_pos - 1].end();
+        self.pos = as_pos + 1;
+        let sql = Spanned::new(self.script[select.start..sql_end].to_string(), select.start..sql_end);
+        let table = self.name("a table name")?;
+        Ok(StatementKind::Select { sql, table })
+    }
+
+    fn save(&mut self) -> ParseResult<StatementKind> {
+        self.expect_word("save")?;
+        let mode = match self.peek().map(|t| (t.text.to_lowercase(), t.start..t.end())) {
+            Some((word, span)) => {
+                let mode = match word.as_str() {
+                    "overwrite" => Some(SaveMode::Overwrite),
+                    "append" => Some(SaveMode::Append),
+                    "errorifexists" => Some(SaveMode::ErrorIfExists),
+                    "ignore" => Some(SaveMode::Ignore),
+                    _ => None,
+                };
+                if mode.is_some() {
+                    self.bump();
+                }
+                mode.map(|mode| Spanned::new(mode, span))
+            }
+            None => None,
+        };
+        let table = self.name("a table name")?;
+        self.expect_word("as")?;
+        let target = self.source()?;
+        let options = self.options()?;
+
+        let mut partition_by = vec![];
+        if self.at_word("partitionBy") {
+            self.bump();
+            partition_by.push(self.name("a column name")?);
+            while self.at_punct(",") {
+                self.bump();
+                partition_by.push(self.name("a column name")?);
+            }
+        }
+        let mut coalesce = None;
+        if self.at_word("coalesce") {
+            self.bump();
+            match self.peek() {
+                Some(token) if token.kind == TokenKind::Number => {
+                    coalesce = Some(Spanned::new(token.text.to_string(), token.start..token.end()));
+                    self.bump();
+                }
+                _ => return self.error(String::from("expected the number of partitions")),
+            }
+        }
+        Ok(StatementKind::Save {
+            mode,
+            table,
+            target,
+            options,
+            partition_by,
+            coalesce,
+        })
+    }
+
+    fn connect(&mut self) -> ParseResult<StatementKind> {
+        self.expect_word("connect")?;
+        let format = self.name("a format")?;
+        let options = self.options()?;
+        let alias = if self.at_word("as") {
+            self.bump();
+            Some(self.name("a connection name")?)
+        } else {
+            None
+        };
+        Ok(StatementKind::Connect { format, options, alias })
+    }
+
+    fn set(&mut self) -> ParseResult<StatementKind> {
+        self.expect_word("set")?;
+        let key = self.key()?;
+        self.expect_punct("=")?;
+        let value = self.value()?;
+        let options = self.options()?;
+        Ok(StatementKind::Set { key, value, options })
+    }
+
+    fn train(&mut self, kind: TrainKind) -> ParseResult<StatementKind> {
+        self.bump();
+        let table = self.name("a table name")?;
+        self.expect_word("as")?;
+        let model = self.source()?;
+        let options = self.options()?;
+        let output = if self.at_word("as") {
+            self.bump();
+            Some(self.name("a table name")?)
+        } else {
+            None
+        };
+        Ok(StatementKind::Train {
+            kind,
+            table,
+            model,
+            options,
+            output,
+        })
+    }
+
+    fn register(&mut self) -> ParseResult<StatementKind> {
+        self.expect_word("register")?;
+        let source = self.source()?;
+        self.expect_word("as")?;
+        let function = self.name("a function name")?;
+        let options = self.options()?;
+        Ok(StatementKind::Register {
+            source,
+            function,
+            options,
+        })
+    }
+
+    fn include(&mut self) -> ParseResult<StatementKind> {
+        self.expect_word("include")?;
+        let source = self.source()?;
+        let options = self.options()?;
+        Ok(StatementKind::Include { source, options })
+    }
+
+    /// a `!` command, or a whole `!if` block.
+    fn command(&mut self) -> ParseResult<StatementKind> {
+        let token = self.bump().unwrap();
+        if token.text.eq_ignore_ascii_case("!if") {
+            return Ok(self.if_block(token));
+        }
+        let name = Name::new(token.text[1..].to_string(), token.start + 1..token.end());
+        let args = self.command_args();
+        Ok(StatementKind::Command { name, args })
+    }
+
+    /// the arguments are separated by whitespace, so `/tmp/a` is one argument
+    /// although it is several tokens.
+    fn command_args(&mut self) -> Vec<Spanned<String>> {
+        let mut args: Vec<Spanned<String>> = vec![];
+        while !self.at_end_of_statement() {
+            let token = self.bump().unwrap();
+            match args.last_mut() {
+                Some(arg) if arg.span.end == token.start => {
+                    arg.node.push_str(token.text);
+                    arg.span.end = token.end();
+                }
+                _ => args.push(Spanned::new(token.text.to_string(), token.start..token.end())),
+            }
+        }
+        args
+    }
+
+    /// the `!if` token is already consumed. The `;` of the `!fi` is left for
+    /// the statement to consume. Errors in a branch are recorded and the block
+    /// goes on, so that its other branches are still parsed.
+    fn if_block(&mut self, if_token: Token) -> StatementKind {
+        let mut branches = vec![];
+        let mut fi = None;
+        let mut command = if_token;
+        let mut after_else = false;
+        loop {
+            let is_else = command.text.eq_ignore_ascii_case("!else");
+            let condition = self.branch_line(&command, !is_else);
+            let span = command.start..self.tokens[self.pos - 1].end();
+            let body = self.statements(true);
+            let branch_token = match self.peek() {
+                Some(token) => *token,
+                // the branches parsed so far are kept
+                None => {
+                    branches.push(IfBranch { condition, span, body });
+                    self.errors.push(ParseError {
+                        message: String::from("`!if` without `!fi`"),
+                        span: if_token.start..if_token.end(),
+                    });
+                    break;
+                }
+            };
+            self.bump();
+            branches.push(IfBranch { condition, span, body });
+            if branch_token.text.eq_ignore_ascii_case("!fi") {
+                fi = Some(branch_token.start..branch_token.end());
+                break;
+            }
+            after_else |= is_else;
+            if after_else {
+                self.errors.push(ParseError {
+                    message: format!("`{}` after `!else`", branch_token.text),
+                    span: branch_token.start..branch_token.end(),
+                });
+            }
+            command = branch_token;
+        }
+        StatementKind::If { branches, fi }
+    }
+
+    /// the rest of the line of a branch, with the condition of `!if` and
+    /// `!elif`. An error is recorded and the line skipped up to its `;`, the
+    /// condition being kept if it was read.
+    fn branch_line(&mut self, command: &Token, with_condition: bool) -> Option<Spanned<Value>> {
+        let condition = if with_condition {
+            match self.condition(command) {
+                Ok(condition) => Some(condition),
+                Err(e) => {
+                    self.errors.push(e);
+                    self.skip_statement();
+                    return None;
+                }
+            }
+        } else {
+            None
+        };
+        if let Err(e) = self.end_of_statement() {
+            self.errors.push(e);
+            self.skip_statement();
+        }
+        condition
+    }
+
+    fn condition(&mut self, command: &Token) -> ParseResult<Spanned<Value>> {
+        if self.at_end_of_statement() {
+            return self.error(format!("expected the condition of `{}`", command.text));
+        }
+        self.value()
+    }
+
+    /// `format.path`, the path being quoted, or words separated by dots such
+    /// as `db.table`.
+    fn source(&mut self) -> ParseResult<Source> {
+        let format = self.name("a format")?;
+        self.expect_punct(".")?;
+        let path = match self.peek().copied() {
+            Some(token) if token.kind == TokenKind::Word => {
+                let mut end = token.end();
+                self.bump();
+                while self.at_punct(".")
+                    && matches!(self.tokens.get(self.pos + 1), Some(t) if t.kind == TokenKind::Word)
+                {
+                    self.bump();
+                    end = self.bump().unwrap().end();
+                }
+                Spanned::new(Value::Word(self.script[token.start..end].to_string()), token.start..end)
+            }
+            Some(token)
+                if matches!(
+                    token.kind,
+                    TokenKind::Backtick | TokenKind::String | TokenKind::Variable
+                ) =>
+            {
+                self.value()?
+            }
+            _ => return self.error(String::from("expected a path")),
+        };
+        Ok(Source { format, path })
+    }
+
+    /// the optional `where` or `options` clause, pairs separated by `and`.
+    fn options(&mut self) -> ParseResult<Vec<OptionPair>> {
+        if !self.at_word("where") && !self.at_word("options") {
+            return Ok(vec![]);
+        }
+        self.bump();
+        let mut options = vec![];
+        loop {
+            let key = self.key()?;
+            self.expect_punct("=")?;
+            let value = self.value()?;
+            options.push(OptionPair { key, value });
+            if !self.at_word("and") {
+                break;
+            }
+            self.bump();
+        }
+        Ok(options)
+    }
+
+    /// an option key, such as `header`, `fitParam.0.maxIter` or `` `a b` ``.
+    fn key(&mut self) -> ParseResult<Name> {
+        let first = match self.peek().copied() {
+            Some(token) if matches!(token.kind, TokenKind::Word | TokenKind::Backtick | TokenKind::String) => token,
+            _ => return self.error(String::from("expected an option name")),
+        };
+        self.bump();
+        if first.kind != TokenKind::Word {
+            return Ok(Name::new(unquote(&first), first.start..first.end()));
+        }
+        let mut end = first.end();
+        while self.at_punct(".") {
+            self.bump();
+            match self.peek().copied() {
+                Some(token) if matches!(token.kind, TokenKind::Word | TokenKind::Number) => {
+                    end = token.end();
+                    self.bump();
+                }
+                _ => return self.error(String::from("expected an option name")),
+            }
+        }
+        Ok(Name::new(self.script[first.start..end].to_string(), first.start..end))
+    }
+
+    fn value(&mut self) -> ParseResult<Spanned<Value>> {
+        let token = match self.peek().copied() {
+            Some(token) => token,
+            None => return self.error(String::from("expected a value")),
+        };
+        // a sign right before a number, such as `limit=-1`
+        if token.kind == TokenKind::Punct && (token.text == "-" || token.text == "+") {
+            match self.tokens.get(self.pos + 1).copied() {
+                Some(number) if number.kind == TokenKind::Number && number.start == token.end() => {
+                    self.pos += 2;
+                    let span = token.start..number.end();
+                    return Ok(Spanned::new(Value::Number(self.script[span.clone()].to_string()), span));
+                }
+                _ => return self.error(String::from("expected a value")),
+            }
+        }
+        let value = match token.kind {
+            TokenKind::String => Value::String(unquote(&token)),
+            TokenKind::BlockString => Value::BlockString(unquote(&token)),
+            TokenKind::Number => Value::Number(token.text.to_string()),
+            // `where key= as t` misses a value rather than ending with `t`
+            TokenKind::Word if !token.text.eq_ignore_ascii_case("as") && !token.text.eq_ignore_ascii_case("and") => {
+                Value::Word(token.text.to_string())
+            }
+            TokenKind::Backtick => Value::Quoted(unquote(&token)),
+            TokenKind::Variable => Value::Variable(token.text.to_string()),
+            _ => return self.error(String::from("expected a value")),
+        };
+        self.bump();
+        Ok(Spanned::new(value, token.start..token.end()))
+    }
+
+    /// a table, column or function name, quoted with backticks or not.
+    fn name(&mut self, what: &str) -> ParseResult<Name> {
+        match self.peek().copied() {
+            Some(token) if matches!(token.kind, TokenKind::Word | TokenKind::Backtick | TokenKind::Variable) => {
+                self.bump();
+                Ok(Name::new(unquote(&token), token.start..token.end()))
+            }
+            _ => self.error(format!("expected {}", what)),
+        }
+    }
+}
+
+fn is_branch_command(text: &str) -> bool {
+    ["!elif", "!else", "!fi"].iter().any(|c| text.eq_ignore_ascii_case(c))
+}
+
+/// the text of a quoted token without its quotes, as is for other tokens.
+fn unquote(token: &Token) -> String {
+    let quote_len = match token.kind {
+        TokenKind::String | TokenKind::Backtick => 1,
+        TokenKind::BlockString => 3,
+        _ => 0,
+    };
+    let text = token.text;
+    if quote_len == 0 || !token.terminated || text.len() < quote_len * 2 {
+        return text.to_string();
+    }
+    let inner = &text[quote_len..text.len() - quote_len];
+    if token.kind == TokenKind::Backtick {
+        inner.replace("``", "`")
+    } else {
+        inner.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fixtures;
+    use std::fmt::Write;
+
+    fn value(v: &Spanned<Value>) -> String {
+        match &v.node {
+            Value::String(s) => format!("{:?}", s),
+            Value::BlockString(s) => format!("'''{}'''", s),
+            Value::Number(s) | Value::Word(s) | Value::Variable(s) => s.clone(),
+            Value::Quoted(s) => format!("`{}`", s),
+        }
+    }
+
+    fn name(n: &Name) -> String {
+        format!("{}@{}..{}", n.node, n.span.start, n.span.end)
+    }
+
+    fn source(s: &Source) -> String {
+        format!("{}.{}", name(&s.format), value(&s.path))
+    }
+
+    fn options(out: &mut String, indent: &str, options: &[OptionPair]) {
+        for pair in options {
+            writeln!(out, "{}  option {} = {}", indent, name(&pair.key), value(&pair.value)).unwrap();
+        }
+    }
+
+    /// one line per statement and per part of it, nested statements indented.
+    fn dump(out: &mut String, indent: &str, statements: &[Statement]) {
+        for statement in statements {
+            let span = format!("@{}..{}", statement.span.start, statement.span.end);
+            match &statement.kind {
+                StatementKind::Load {
+                    source: s,
+                    options: o,
+                    table,
+                } => {
+                    writeln!(out, "{}load {} {} as {}", indent, span, source(s), name(table)).unwrap();
+                    options(out, indent, o);
+                }
+                StatementKind::Select { sql, table } => {
+                    writeln!(out, "{}select {} {:?} as {}", indent, span, sql.node, name(table)).unwrap();
+                }
+                StatementKind::Save {
+                    mode,
+                    table,
+                    target,
+                    options: o,
+                    partition_by,
+                    coalesce,
+                } => {
+                    let mode = mode.as_ref().map(|m| format!(" {:?}", m.node)).unwrap_or_default();
+                    writeln!(
+                        out,
+                        "{}save {}{} {} as {}",
+                        indent,
+                        span,
+                        mode,
+                        name(table),
+                        source(target)
+                    )
+                    .unwrap();
+                    options(out, indent, o);
+                    for column in partition_by {
+                        writeln!(out, "{}  partitionBy {}", indent, name(column)).unwrap();
+                    }
+                    if let Some(n) = coalesce {
+                        writeln!(out, "{}  coalesce {}", indent, n.node).unwrap();
+                    }
+                }
+                StatementKind::Connect {
+                    format,
+                    options: o,
+                    alias,
+                } => {
+                    let alias = alias.as_ref().map(|a| format!(" as {}", name(a))).unwrap_or_default();
+                    writeln!(out, "{}connect {} {}{}", indent, span, name(format), alias).unwrap();
+                    options(out, indent, o);
+                }
+                StatementKind::Set {
+                    key,
+                    value: v,
+                    options: o,
+                } => {
+                    writeln!(out, "{}set {} {} = {}", indent, span, name(key), value(v)).unwrap();
+                    options(out, indent, o);
+                }
+                StatementKind::Train {
+                    kind,
+                    table,
+                    model,
+                    options: o,
+                    output,
+                } => {
+                    let output = output.as_ref().map(|a| format!(" as {}", name(a))).unwrap_or_default();
+                    writeln!(
+                        out,
+                        "{}{:?} {} {} as {}{}",
+                        indent,
+                        kind,
+                        span,
+                        name(table),
+                        source(model),
+                        output
+                    )
+                    .unwrap();
+                    options(out, indent, o);
+                }
+                StatementKind::Register {
+                    source: s,
+                    function,
+                    options: o,
+                } => {
+                    writeln!(out, "{}register {} {} as {}", indent, span, source(s), name(function)).unwrap();
+                    options(out, indent, o);
+                }
+                StatementKind::Include { source: s, options: o } => {
+                    writeln!(out, "{}include {} {}", indent, span, source(s)).unwrap();
+                    options(out, indent, o);
+                }
+                StatementKind::Sql { keyword } => {
+                    writeln!(out, "{}sql {} {}", indent, span, name(keyword)).unwrap();
+                }
+                StatementKind::Command { name: n, args } => {
+                    let args = args.iter().map(|a| a.node.as_str()).collect::<Vec<_>>().join(" ");
+                    writeln!(out, "{}command {} {} [{}]", indent, span, name(n), args).unwrap();
+                }
+                StatementKind::If { branches, fi } => {
+                    writeln!(out, "{}if {}", indent, span).unwrap();
+                    for branch in branches {
+                        let span = format!("@{}..{}", branch.span.start, branch.span.end);
+                        match &branch.condition {
+                            Some(condition) => {
+                                writeln!(out, "{}  when {} {}", indent, span, value(condition)).unwrap()
+                            }
+                            None => writeln!(out, "{}  else {}", indent, span).unwrap(),
+                        }
+                        dump(out, &format!("{}    ", indent), &branch.body);
+                    }
+                    if let Some(fi) = fi {
+                        writeln!(out, "{}  fi @{}..{}", indent, fi.start, fi.end).unwrap();
+                    }
+                }
+                StatementKind::Invalid => writeln!(out, "{}invalid {}", indent, span).unwrap(),
+            }
+        }
+    }
+
+    fn render(script: &Script) -> String {
+        let mut out = String::new();
+        dump(&mut out, "", &script.statements);
+        for error in &script.errors {
+            writeln!(
+                out,
+                "error @{}..{}: {}",
+                error.span.start, error.span.end, error.message
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    /// every `tests/fixtures/parser/*.byzer` is parsed and compared with the
+    /// `.ast` file next to it.
+    #[test]
+    fn fixtures() {
+        fixtures::check("parser", "ast", |script| render(&parse(script)), |_| Ok(()));
+    }
+
+    #[test]
+    fn spans_point_into_the_script() {
+        let script = "load csv.`/tmp/a.csv` where header=\"true\" as t;";
+        let parsed = parse(script);
+        assert!(parsed.errors.is_empty());
+        match &parsed.statements[0].kind {
+            StatementKind::Load { source, options, table } => {
+                assert_eq!(&script[source.path.span.clone()], "`/tmp/a.csv`");
+                assert_eq!(&script[options[0].value.span.clone()], "\"true\"");
+                assert_eq!(&script[table.span.clone()], "t");
+            }
+            kind => panic!("unexpected {:?}", kind),
+        }
+        assert_eq!(parsed.statements[0].span, 0..script.len());
+    }
+
+    #[test]
+    fn recover_after_errors() {
+        let parsed = parse("load csv as t;\nselect 1 as a;\nfoo bar;\nselect 2 as b;");
+        assert_eq!(parsed.errors.len(), 2);
+        let kinds = parsed
+            .statements
+            .iter()
+            .map(|s| matches!(s.kind, StatementKind::Invalid))
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, vec![true, false, true, false]);
+    }
+}
@@ -127,19 +127,19 @@ fn json_to_yaml(value: &Value) -> Yaml {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum PlainTextTableFormat {
     Default,
     Markdown,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum HtmlTableFormat {
     Raw,
     Styled,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum TableFormat {
     PlainText(PlainTextTableFormat),
     Html(HtmlTableFormat),
@@ -1,12 +1,22 @@
 use std::sync::mpsc::{channel, Sender};
 use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
 use std::thread::{self, sleep};
 use std::time::Duration;
 
+/// how a task watched by `ExecutingProgressBar` ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecutingState {
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
 /// a progress bar to indicate the progress of starting the engine
 /// or executing a single task.
 pub struct ExecutingProgressBar {
-    finish_signal: Option<Sender<bool>>,
+    finish_signal: Option<Sender<ExecutingState>>,
 }
 
 impl ExecutingProgressBar {
@@ -18,7 +28,7 @@ impl ExecutingProgressBar {
 
     /// start a child thread to monitor the execution, waiting for the finish signal.
     pub fn start_monitor(&mut self, prefix: String) -> thread::JoinHandle<()> {
-        let (tx, rx) = channel::<bool>();
+        let (tx, rx) = channel::<ExecutingState>();
         self.finish_signal = Some(tx);
         let pb = ProgressBar::new_spinner();
         pb.set_style(
@@ -31,12 +41,12 @@ impl ExecutingProgressBar {
             loop {
                 pb.set_message(format!("{} {}s", prefix, count));
                 match rx.try_recv() {
-                    Ok(val) => {
+                    Ok(state) => {
                         pb.finish_and_clear();
-                        if val {
-                            println!("✅ {}s.", count);
-                        } else {
-                            println!("❌ {}s.", count);
+                        match state {
+                            ExecutingState::Succeeded => println!("✅ {}s.", count),
+                            ExecutingState::Failed => println!("❌ {}s.", count),
+                            ExecutingState::Cancelled => println!("🚫 {}s, cancelled.", count),
                         }
                         break;
                     },
@@ -54,8 +64,21 @@ impl ExecutingProgressBar {
     /// send finish signal to monitor thread, send false if finished with error
     /// , vice versa.
     pub fn send_finish_signal(&self, val: bool) {
+        self.send_state(if val {
+            ExecutingState::Succeeded
+        } else {
+            ExecutingState::Failed
+        });
+    }
+
+    /// send finish signal to monitor thread when the task was cancelled by the user.
+    pub fn send_cancel_signal(&self) {
+        self.send_state(ExecutingState::Cancelled);
+    }
+
+    fn send_state(&self, state: ExecutingState) {
         if let Some(f) = &self.finish_signal {
-            f.send(val).unwrap();
+            f.send(state).unwrap();
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,76 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::utils::conf::ByzerConf;
+use crate::utils::progress_bar::ExecutingState;
+
+/// What the prompt tells about the statements executed so far.
+#[derive(Default)]
+pub struct PromptState {
+    statements: usize,
+    last: Option<(ExecutingState, Duration)>,
+}
+
+impl PromptState {
+    pub fn record(&mut self, state: ExecutingState, elapsed: Duration) {
+        self.statements += 1;
+        self.last = Some((state, elapsed));
+    }
+}
+
+/// The prompt shown before a statement. The template may use these
+/// placeholders:
+///
+/// * `{engine}`: the host and port of the engine
+/// * `{owner}`: the user the scripts run as
+/// * `{n}`: the number of the statement being typed
+/// * `{status}`: ✅ or ❌ for the last statement, empty before the first one
+/// * `{duration}`: how long the last statement took, such as `1.25s`
+pub struct Prompt {
+    template: String,
+    engine: String,
+    owner: String,
+    state: Arc<Mutex<PromptState>>,
+}
+
+impl Prompt {
+    /// ```text
+    /// shell.prompt=>>
+    /// ```
+    ///
+    /// A trailing space is added when missing, as the conf file trims its
+    /// values.
+    pub fn new(byzer_conf: &ByzerConf) -> Self {
+        let mut template = byzer_conf.shell_config_or("prompt", String::from(">>"));
+        if !template.ends_with(' ') {
+            template.push(' ');
+        }
+        let engine = byzer_conf.engine_url.as_str();
+        Self {
+            template,
+            engine: engine.split_once("://").map(|(_, host)| host).unwrap_or(engine).to_string(),
+            owner: byzer_conf.owner.clone(),
+            state: Arc::new(Mutex::new(PromptState::default())),
+        }
+    }
+
+    /// updated as statements are executed.
+    pub fn state(&self) -> Arc<Mutex<PromptState>> {
+        Arc::clone(&self.state)
+    }
+
+    pub fn render(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let (status, duration) = match state.last {
+            Some((ExecutingState::Succeeded, elapsed)) => ("✅", format!("{:.2}s", elapsed.as_secs_f64())),
+            Some((_, elapsed)) => ("❌", format!("{:.2}s", elapsed.as_secs_f64())),
+            None => ("", String::new()),
+        };
+        self.template
+            .replace("{engine}", self.engine.as_str())
+            .replace("{owner}", self.owner.as_str())
+            .replace("{n}", (state.statements + 1).to_string().as_str())
+            .replace("{status}", status)
+            .replace("{duration}", duration.as_str())
+    }
+}
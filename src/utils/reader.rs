@@ -40,25 +40,28 @@ impl<R: BufRead> StreamingValueReader<R> {
     pub fn new(buf_read: R) -> StreamingValueReader<R> {
         StreamingValueReader { buf_read }
     }
+
+    /// the rows, parsed one by one while they are read. The input is either a
+    /// json array, which is what the engine returns, or newline-delimited json.
+    pub fn rows(self) -> Rows<R> {
+        Rows {
+            buf_read: self.buf_read,
+            mode: None,
+            done: false,
+        }
+    }
 }
 
 impl<R: BufRead> ValueReader for StreamingValueReader<R> {
     fn read_value(self, take: Option<usize>) -> GenericResult<Value> {
         let take = take.unwrap_or(100);
         let elements: Vec<Value> = self
-            .buf_read
-            .lines()
+            .rows()
             .take(take)
-            .flat_map(|line| match line {
-                Ok(line) => match serde_json::from_str::<Value>(line.as_str()) {
-                    Ok(parsed) => Some(parsed),
-                    Err(err) => {
-                        eprintln!("error parsing row: {}", err.to_string());
-                        None
-                    }
-                },
+            .flat_map(|row| match row {
+                Ok(parsed) => Some(parsed),
                 Err(err) => {
-                    eprintln!("error reading row: {}", err.to_string());
+                    eprintln!("error parsing row: {}", err);
                     None
                 }
             })
@@ -67,3 +70,195 @@ impl<R: BufRead> ValueReader for StreamingValueReader<R> {
         Ok(Value::Array(elements))
     }
 }
+
+enum RowsMode {
+    /// elements of a top-level json array
+    Array,
+    /// one json value per line
+    Lines,
+}
+
+pub struct Rows<R: BufRead> {
+    buf_read: R,
+    mode: Option<RowsMode>,
+    done: bool,
+}
+
+impl<R: BufRead> Rows<R> {
+    fn peek(&mut self) -> GenericResult<Option<u8>> {
+        Ok(self.buf_read.fill_buf()?.first().copied())
+    }
+
+    fn skip_while<F: Fn(u8) -> bool>(&mut self, f: F) -> GenericResult<()> {
+        while let Some(b) = self.peek()? {
+            if !f(b) {
+                break;
+            }
+            self.buf_read.consume(1);
+        }
+        Ok(())
+    }
+
+    fn detect_mode(&mut self) -> GenericResult<RowsMode> {
+        self.skip_while(|b| b.is_ascii_whitespace())?;
+        if self.peek()? == Some(b'[') {
+            self.buf_read.consume(1);
+            Ok(RowsMode::Array)
+        } else {
+            Ok(RowsMode::Lines)
+        }
+    }
+
+    /// read the bytes of the next array element, tracking strings and nesting
+    /// to find the `,` or `]` which ends it.
+    fn next_element(&mut self) -> GenericResult<Option<Vec<u8>>> {
+        self.skip_while(|b| b.is_ascii_whitespace() || b == b',')?;
+        match self.peek()? {
+            None => return Err("unexpected end of json array".into()),
+            Some(b']') => {
+                self.buf_read.consume(1);
+                return Ok(None);
+            }
+            _ => {}
+        }
+
+        let mut element = vec![];
+        let (mut depth, mut in_string, mut escaped) = (0usize, false, false);
+        while let Some(b) = self.peek()? {
+            if in_string {
+                match b {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => {}
+                }
+            } else {
+                match b {
+                    b'"' => in_string = true,
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' if depth > 0 => depth -= 1,
+                    b',' | b']' if depth == 0 => return Ok(Some(element)),
+                    _ => {}
+                }
+            }
+            element.push(b);
+            self.buf_read.consume(1);
+        }
+        Err("unexpected end of json array".into())
+    }
+
+    fn next_line(&mut self) -> GenericResult<Option<String>> {
+        loop {
+            let mut line = String::new();
+            if self.buf_read.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            if !line.trim().is_empty() {
+                return Ok(Some(line));
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Rows<R> {
+    type Item = GenericResult<Value>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if self.mode.is_none() {
+            match self.detect_mode() {
+                Ok(mode) => self.mode = Some(mode),
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+
+        let row = match self.mode {
+            Some(RowsMode::Array) => self
+                .next_element()
+                .and_then(|e| Ok(e.map(|e| serde_json::from_slice::<Value>(&e)).transpose()?)),
+            _ => self
+                .next_line()
+                .and_then(|l| Ok(l.map(|l| serde_json::from_str::<Value>(&l)).transpose()?)),
+        };
+        match row {
+            Ok(Some(value)) => Some(Ok(value)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                // a broken element can not be skipped reliably, stop here
+                self.done = matches!(self.mode, Some(RowsMode::Array));
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// the rows of `input` read through buffers of every size up to its length,
+    /// so that each byte ends a chunk at least once.
+    fn rows_by_chunks(input: &str) -> Vec<Vec<Value>> {
+        (1..=input.len())
+            .map(|capacity| {
+                StreamingValueReader::new(BufReader::with_capacity(capacity, input.as_bytes()))
+                    .rows()
+                    .collect::<GenericResult<Vec<_>>>()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stream_array_elements() {
+        let input = r#" [{"a":[1,[2,{"b":"]"}]],"c":{"d":{}}}, {"e":"x\"y,z"} ,{"f":"\\"},{"g":"\\\"]"},[],"}"]"#;
+        let expected = vec![
+            json!({"a": [1, [2, {"b": "]"}]], "c": {"d": {}}}),
+            json!({"e": "x\"y,z"}),
+            json!({"f": "\\"}),
+            json!({"g": "\\\"]"}),
+            json!([]),
+            json!("}"),
+        ];
+        for rows in rows_by_chunks(input) {
+            assert_eq!(rows, expected);
+        }
+    }
+
+    #[test]
+    fn stream_empty_array() {
+        for input in ["[]", " [ \n ] "] {
+            for rows in rows_by_chunks(input) {
+                assert!(rows.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn stream_lines() {
+        let input = "{\"a\":1}\n\n{\"b\":\"\\\\\"}\n";
+        for rows in rows_by_chunks(input) {
+            assert_eq!(rows, vec![json!({"a": 1}), json!({"b": "\\"})]);
+        }
+    }
+
+    #[test]
+    fn stop_at_truncated_array() {
+        let mut rows = StreamingValueReader::new(BufReader::new(r#"[{"a":1},{"b":"#.as_bytes())).rows();
+        assert_eq!(rows.next().unwrap().unwrap(), json!({"a": 1}));
+        assert!(rows.next().unwrap().is_err());
+        assert!(rows.next().is_none());
+    }
+}
@@ -0,0 +1,455 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use colored::*;
+
+use crate::utils::commands::{CommandRegistry, Flow};
+use crate::utils::diagnostic;
+use crate::utils::engine::EngineClient;
+use crate::utils::external_editor::ExternalEditor;
+use crate::utils::formatter;
+use crate::utils::history::History;
+use crate::utils::include::{IncludeResolver, SourceMap};
+use crate::utils::jobs::{abbreviate, async_script, JobManager};
+use crate::utils::lexer;
+use crate::utils::lint::{self, Linter};
+use crate::utils::pager::ResultPager;
+use crate::utils::parser::{self, Statement, StatementKind};
+use crate::utils::progress_bar::ExecutingState;
+use crate::utils::prompt::PromptState;
+use crate::utils::set_output_format;
+use crate::utils::vars::Variables;
+
+/// Why a script can't be sent, with the message or the rendered lints.
+pub enum Unresolved {
+    Includes(String),
+    Variables(String),
+}
+
+/// `script` with its local includes inlined and then its variables applied,
+/// as it is sent to the engine, and where its lines come from. `origin` names
+/// the script in the lints of undefined variables.
+pub fn resolve_script(
+    includes: &IncludeResolver,
+    vars: &Variables,
+    script: &str,
+    origin: Option<&str>,
+) -> Result<(String, Option<SourceMap>), Unresolved> {
+    let (resolved, source_map) = if includes.enabled {
+        includes.resolve(script).map_err(Unresolved::Includes)?
+    } else {
+        (script.to_string(), None)
+    };
+    let script = vars.apply(resolved.as_str()).map_err(|missing| {
+        Unresolved::Variables(missing.iter().map(|lint| format!("{}\n", lint.render(&resolved, origin))).collect())
+    })?;
+    let source_map = source_map.map(|mut source_map| {
+        // `set` statements may have been added before the script
+        source_map.shift(script.matches('\n').count().saturating_sub(resolved.matches('\n').count()));
+        source_map
+    });
+    Ok((script, source_map))
+}
+
+/// Whether a script is sent as one request, or statement by statement with
+/// `shell.execute.split` or `:split`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitMode {
+    Off,
+    /// only the result of the last statement is printed
+    Last,
+    /// the results of the statements ending with `as <table>` are printed too
+    All,
+}
+
+impl FromStr for SplitMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "off" | "false" => Ok(SplitMode::Off),
+            "last" | "true" => Ok(SplitMode::Last),
+            "all" => Ok(SplitMode::All),
+            _ => Err(format!("unknown split mode: {}", s)),
+        }
+    }
+}
+
+/// What the meta commands act on: the engine, the result pager, the async jobs
+/// and the history of the session.
+pub struct Repl {
+    pub engine: Arc<EngineClient>,
+    pub pager: ResultPager,
+    pub jobs: JobManager,
+    pub history: History,
+    pub editor: ExternalEditor,
+    pub prompt_state: Arc<Mutex<PromptState>>,
+    /// whether the time a statement took is printed after its result
+    pub timing: bool,
+    pub split: SplitMode,
+    pub linter: Linter,
+    pub vars: Variables,
+    pub includes: IncludeResolver,
+}
+
+impl Repl {
+    pub fn new(
+        engine: Arc<EngineClient>,
+        pager: ResultPager,
+        jobs: JobManager,
+        history: History,
+        editor: ExternalEditor,
+        prompt_state: Arc<Mutex<PromptState>>,
+    ) -> Self {
+        Self {
+            engine,
+            pager,
+            jobs,
+            history,
+            editor,
+            prompt_state,
+            timing: false,
+            split: SplitMode::Off,
+            linter: Linter::default(),
+            vars: Variables::default(),
+            includes: IncludeResolver::default(),
+        }
+    }
+
+    /// handle a line entered at the prompt, a command when it starts with `:`
+    /// or else a script for the engine.
+    pub fn handle(&mut self, commands: &CommandRegistry<Repl>, line: &str) -> Flow {
+        self.jobs.report_finished();
+
+        let command = match line.trim().strip_prefix(':') {
+            Some(command) => command,
+            None => {
+                self.execute_recorded(line);
+                return Flow::Continue;
+            }
+        };
+        let flow = commands.run(self, command);
+        // `:rerun` and `:edit` record the script they execute instead, and
+        // `:quit` also stands for Ctrl-D
+        if !matches!(command.split_whitespace().next(), Some("rerun") | Some("edit") | Some("quit")) {
+            self.history.record(line, None, None);
+        }
+        flow
+    }
+
+    /// execute `script` and add it to the history.
+    pub fn execute_recorded(&mut self, script: &str) {
+        let state = self.execute(script);
+        self.history.record(script, state.map(|(_, elapsed)| elapsed), state.map(|(state, _)| state));
+    }
+
+    /// execute `script`, or submit it when it ends with `&`. Returns how it
+    /// ended and how long it took when it ran in the foreground.
+    pub fn execute(&mut self, script: &str) -> Option<(ExecutingState, Duration)> {
+        let (script, source_map) = self.prepare(script)?;
+        let script = script.as_str();
+        if let Some(script) = async_script(script) {
+            self.jobs.submit(script, source_map);
+            return None;
+        }
+        let started = Instant::now();
+        let state = if self.split == SplitMode::Off || lexer::split_statements(script).len() < 2 {
+            self.pager.run(script, source_map.as_ref())
+        } else {
+            self.execute_split(script, source_map.as_ref())
+        };
+        let elapsed = started.elapsed();
+        self.prompt_state.lock().unwrap().record(state, elapsed);
+        if self.timing {
+            println!("Time: {:.3}s", elapsed.as_secs_f64());
+        }
+        Some((state, elapsed))
+    }
+
+    /// the script to send for `script`, with its local includes inlined and
+    /// its variables applied, and where its lines come from when it has
+    /// includes. `None` if it must not be sent, the reason being printed.
+    fn prepare(&self, script: &str) -> Option<(String, Option<SourceMap>)> {
+        let (script, source_map) = match resolve_script(&self.includes, &self.vars, script, None) {
+            Ok(resolved) => resolved,
+            Err(Unresolved::Includes(e)) => {
+                println!("{}\nNot executed, the includes can't be resolved.", e);
+                return None;
+            }
+            Err(Unresolved::Variables(e)) => {
+                println!("{}Not executed, the script uses undefined variables.", e);
+                return None;
+            }
+        };
+        if self.linter.block && self.lint(async_script(&script).unwrap_or(&script)) {
+            println!("Not executed, the script has errors.");
+            return None;
+        }
+        Some((script, source_map))
+    }
+
+    /// print the lints of `script` and tell whether there is an error among them.
+    fn lint(&self, script: &str) -> bool {
+        let lints = self.linter.lint(script, &self.pager.session_tables());
+        lint::print_lints(&lints, script, None)
+    }
+
+    /// execute the statements of `script` one by one, each under its own
+    /// spinner, until one of them fails. The engine forgets `set` variables
+    /// between requests, so the `set` statements executed so far are sent again
+    /// with every statement.
+    fn execute_split(&mut self, script: &str, source_map: Option<&SourceMap>) -> ExecutingState {
+        let spans = lexer::statement_spans(script);
+        let line_of = |pos: usize| script[..pos].matches('\n').count() + 1;
+        let mut sets = String::new();
+        // the first line and the number of lines of the `set` statements
+        let mut set_lines = vec![];
+        for (i, span) in spans.iter().enumerate() {
+            let statement = &script[span.clone()];
+            let is_last = i + 1 == spans.len();
+            println!("{} {}", format!("[{}/{}]", i + 1, spans.len()).bold(), abbreviate(statement));
+            let request = format!("{}{}", sets, statement);
+            let lines = (line_of(span.start), statement.matches('\n').count() + 1);
+            let request_map = source_map.map(|source_map| {
+                let pieces = set_lines.iter().copied().chain([lines]).collect::<Vec<_>>();
+                source_map.pieces(&pieces)
+            });
+            let show = is_last || (self.split == SplitMode::All && lexer::output_table(statement).is_some());
+            let state = if show {
+                self.pager.run(request.as_str(), request_map.as_ref())
+            } else {
+                self.pager.run_without_result(request.as_str(), request_map.as_ref())
+            };
+            if state != ExecutingState::Succeeded {
+                if !is_last {
+                    println!("Stopped, {} statement(s) not executed.", spans.len() - i - 1);
+                }
+                return state;
+            }
+            if is_set(statement) {
+                sets.push_str(statement);
+                sets.push('\n');
+                set_lines.push(lines);
+            }
+        }
+        ExecutingState::Succeeded
+    }
+}
+
+/// whether `statement` is a `set`, comments before it included.
+fn is_set(statement: &str) -> bool {
+    matches!(
+        parser::parse(statement).statements.first(),
+        Some(Statement { kind: StatementKind::Set { .. }, .. })
+    )
+}
+
+/// ask `question` on stdin, only `y` or `yes` is a yes. `on_eof` is the answer
+/// when stdin is closed, since nothing can be asked again.
+fn confirm(question: &str, on_eof: bool) -> bool {
+    print!("{} [y/N] ", question);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    match io::stdin().read_line(&mut answer) {
+        Ok(0) | Err(_) => {
+            println!();
+            on_eof
+        }
+        Ok(_) => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+    }
+}
+
+/// the commands of the shell.
+pub fn commands() -> CommandRegistry<Repl> {
+    let mut registry = CommandRegistry::<Repl>::new();
+    registry
+        .register("quit", "", "exit the shell", |repl, _| {
+            let running = repl.jobs.running();
+            if running == 0 {
+                Ok(Flow::Quit)
+            } else if confirm(&format!("{} async job(s) still running, exit anyway?", running), true) {
+                repl.jobs.kill_running();
+                Ok(Flow::Quit)
+            } else {
+                Ok(Flow::Continue)
+            }
+        })
+        .register("clear", "", "clear the screen", |_, _| {
+            print!("\x1B[2J\x1B[1;1H");
+            Ok(Flow::Continue)
+        })
+        .register("source", "<file>", "execute the script in a local file", |repl, args| {
+            let script = fs::read_to_string(args[0]).map_err(|e| format!("fail to read {}: {}", args[0], e))?;
+            repl.execute(script.as_str());
+            Ok(Flow::Continue)
+        })
+        .register("timing", "on|off", "print how long each statement takes", |repl, args| {
+            repl.timing = args[0] == "on";
+            Ok(Flow::Continue)
+        })
+        .register(
+            "format",
+            "default|markdown|html|html-raw",
+            "how results are printed",
+            |_, args| {
+                set_output_format(args[0])?;
+                Ok(Flow::Continue)
+            },
+        )
+        .register(
+            "split",
+            "off|last|all",
+            "send scripts statement by statement, printing the last result or every table",
+            |repl, args| {
+                repl.split = args[0].parse()?;
+                Ok(Flow::Continue)
+            },
+        )
+        .register("lint", "[script]", "check the script, or the last statement, for mistakes", |repl, args| {
+            let script = match args.first() {
+                Some(script) => script.to_string(),
+                None => repl.history.last_statement().unwrap_or_default().to_string(),
+            };
+            let lints = repl.linter.lint(script.as_str(), &repl.pager.session_tables());
+            if lints.is_empty() {
+                println!("No mistakes found.");
+            }
+            lint::print_lints(&lints, script.as_str(), None);
+            Ok(Flow::Continue)
+        })
+        .register("fmt", "[script]", "print the script, or the last statement, formatted", |repl, args| {
+            let script = match args.first() {
+                Some(script) => script.to_string(),
+                None => repl.history.last_statement().unwrap_or_default().to_string(),
+            };
+            print!("{}", formatter::format(script.as_str())?);
+            Ok(Flow::Continue)
+        })
+        .register(
+            "var",
+            "[key] [value]",
+            "list the local variables, show one, or set it for `${key}`",
+            |repl, args| {
+                match args {
+                    [] => repl.vars.print(),
+                    [key] => match repl.vars.get(key) {
+                        Some(value) => println!("{}", value),
+                        None => return Err(format!("no variable `{}`", key)),
+                    },
+                    [key, value, ..] => repl.vars.set(key, value)?,
+                }
+                Ok(Flow::Continue)
+            },
+        )
+        .register("more", "", "print the next page of the last result", |repl, _| {
+            repl.pager.more();
+            Ok(Flow::Continue)
+        })
+        .register("async", "<script>", "submit the script as a background job", |repl, args| {
+            if let Some((script, source_map)) = repl.prepare(args[0]) {
+                repl.jobs.submit(&script, source_map);
+            }
+            Ok(Flow::Continue)
+        })
+        .register("jobs", "", "list the running jobs", |repl, _| {
+            repl.jobs.print_jobs();
+            Ok(Flow::Continue)
+        })
+        .register("wait", "<job>", "block until the job finishes and print its result", |repl, args| {
+            repl.jobs.with_job(args[0], |id| repl.jobs.wait(id));
+            Ok(Flow::Continue)
+        })
+        .register("kill", "<job>", "cancel the job", |repl, args| {
+            repl.jobs.with_job(args[0], |id| repl.jobs.kill(id));
+            Ok(Flow::Continue)
+        })
+        .register("result", "<job>", "print the result of a finished job", |repl, args| {
+            repl.jobs.with_job(args[0], |id| repl.jobs.print_result(id));
+            Ok(Flow::Continue)
+        })
+        .register("history", "[pattern]", "list the history, or the entries containing the pattern", |repl, args| {
+            repl.history.print(args.first().copied().unwrap_or_default());
+            Ok(Flow::Continue)
+        })
+        .register("rerun", "<n>", "execute the nth entry listed by `:history` again", |repl, args| {
+            let n = args[0].parse().map_err(|_| format!("no history entry {}", args[0]))?;
+            let script = repl.history.get(n).map_err(|e| format!("entry {}: {}", n, e))?.to_string();
+            println!("{}\n", script);
+            repl.execute_recorded(script.as_str());
+            Ok(Flow::Continue)
+        })
+        .register("edit", "", "edit the last statement in $VISUAL or $EDITOR", |repl, _| {
+            let last = repl
+                .history
+                .last_executable_statement()
+                .map_err(|e| format!("the last statement: {}", e))?
+                .unwrap_or_default()
+                .to_string();
+            let script = repl.editor.edit(last.as_str())?;
+            if script.trim().is_empty() {
+                return Ok(Flow::Continue);
+            }
+            if !repl.editor.submit {
+                return Ok(Flow::Edit(script));
+            }
+            println!("{}\n", script);
+            repl.execute_recorded(script.as_str());
+            Ok(Flow::Continue)
+        })
+        .register(
+            "trace",
+            "[on|off] [file]",
+            "print the stack trace of the last error, or trace the requests to a file",
+            |repl, args| {
+                match args.first().copied() {
+                    Some("on") => match repl.engine.tracer().enable(args.get(1).map(Path::new)) {
+                        Ok(path) => println!("Tracing requests to {}", path.display()),
+                        Err(e) => println!("Fail to open the trace file: {}", e),
+                    },
+                    Some(_) => {
+                        if args.len() > 1 {
+                            return Err(format!("unexpected `{}`", args[1]));
+                        }
+                        repl.engine.tracer().disable();
+                        println!("Tracing is off.");
+                    }
+                    None => diagnostic::print_last_trace(),
+                }
+                Ok(Flow::Continue)
+            },
+        );
+    registry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_set_statements() {
+        assert!(is_set("set a=\"1\";"));
+        assert!(is_set("-- note\nset a=\"1\";"));
+        assert!(is_set("/* a */ SET a=`b` where type=\"shell\";"));
+        assert!(!is_set("select \"set\" as a;"));
+        assert!(!is_set("-- set a=1;\nselect 1 as a;"));
+    }
+
+    #[test]
+    fn resolve_variables() {
+        let includes = IncludeResolver::default();
+        let mut vars = Variables::default();
+        vars.set("day", "2021-01-01").unwrap();
+        let (script, source_map) = resolve_script(&includes, &vars, "select \"${day}\" as a;", None).ok().unwrap();
+        assert_eq!(script, "select \"2021-01-01\" as a;");
+        assert!(source_map.is_none());
+
+        match resolve_script(&includes, &vars, "select ${missing} as a;", Some("a.byzer")) {
+            Err(Unresolved::Variables(e)) => assert!(e.contains("a.byzer:1:8"), "{}", e),
+            _ => panic!("`missing` is not defined"),
+        }
+    }
+}
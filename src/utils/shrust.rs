@@ -1,21 +1,56 @@
 //! A library for creating interactive command line shells
-use rustyline::highlight::Highlighter;
 use colored::*;
+use rustyline::highlight::Highlighter;
 
 use std::string::ToString;
 use std::borrow::Cow;
 use std::borrow::Cow::Borrowed;
 use std::borrow::Cow::Owned;
 
+use rustyline::completion::{Completer, Pair};
 use rustyline::validate::{ValidationContext, ValidationResult, Validator};
-use rustyline_derive::{Completer, Helper, Hinter};
+use rustyline::Context;
+use rustyline::hint::{Hint, Hinter};
+use rustyline_derive::Helper;
+
+use crate::utils::commands::CommandCompleter;
+use crate::utils::completion::{EngineCompleter, LocalCompleter};
+use crate::utils::lexer::{self, TokenKind};
+use crate::utils::theme::Theme;
+use crate::utils::jobs;
 
 use std::collections::HashSet;
-#[derive(Completer, Helper, Hinter)]
+use std::sync::{Arc, Mutex};
+
+/// What the key handlers hand over to the read loop.
+#[derive(Default)]
+pub struct LineBuffer {
+    /// a statement to submit instead of the input, and the rest of the input
+    /// to put back at the prompt after it, set by key bindings
+    pub submitted: Mutex<Option<(String, String)>>,
+}
+
+/// whether `input` is ready to run: a shell command, which takes a single
+/// line except `:async`, or a complete statement.
+pub fn is_statement_complete(input: &str) -> bool {
+    let i = input.trim();
+    if i.starts_with(':') && !i.starts_with(":async") {
+        return true;
+    }
+    // a trailing `&` submits the script as an async job
+    lexer::is_complete(jobs::async_script(input).unwrap_or(input))
+}
+
+#[derive(Helper)]
 pub struct EditHelper {
     _match_script_end_validator: (),
     _highlighter: (),
     sql_keyword_list: HashSet<String>,
+    engine_completer: Option<EngineCompleter>,
+    local_completer: Option<LocalCompleter>,
+    command_completer: Option<CommandCompleter>,
+    theme: Theme,
+    line_buffer: Arc<LineBuffer>,
 }
 
 impl EditHelper {
@@ -28,7 +63,10 @@ impl EditHelper {
                 "exists", "foreign", "from", "full", "group","having", "in", "is", "index", "inner", "into", "join", 
                 "key", "load", "left", "like", "limit", "local", "null", "not", "outer", "or", "order", "primary", 
                 "procedure", "replace", "right", "rownum", "set", "select", "table", "top", "truncate", "unique", "union", 
-                "update", "view", "values", "where", "!if", "!else", "!show"
+                "update", "view", "values", "where", "!if", "!else", "!show",
+                // byzer-lang statements and save modes
+                "train", "run", "predict", "register", "save", "overwrite", "append", "errorifexists",
+                "ignore", "options", "include", "partitionby", "coalesce", "to"
             ].map(|s| s.to_string())
         );
 
@@ -36,81 +74,266 @@ impl EditHelper {
             _match_script_end_validator: (),
             _highlighter: (),
             sql_keyword_list,
+            engine_completer: None,
+            local_completer: None,
+            command_completer: None,
+            theme: Theme::default(),
+            line_buffer: Arc::new(LineBuffer::default()),
         }
     }
+
+    /// complete with the candidates of the engine, the keywords are used
+    /// when the engine is slow or has no suggestion.
+    pub fn set_engine_completer(&mut self, completer: EngineCompleter) -> &mut Self {
+        self.engine_completer = Some(completer);
+        self
+    }
+
+    /// complete paths, `!` commands, and the tables and columns of the session.
+    pub fn set_local_completer(&mut self, completer: LocalCompleter) -> &mut Self {
+        self.local_completer = Some(completer);
+        self
+    }
+
+    /// complete the `:` commands and their arguments.
+    pub fn set_command_completer(&mut self, completer: CommandCompleter) -> &mut Self {
+        self.command_completer = Some(completer);
+        self
+    }
+
+    /// shared with the key handlers.
+    pub fn line_buffer(&self) -> Arc<LineBuffer> {
+        Arc::clone(&self.line_buffer)
+    }
+
+    /// the colors of the syntax highlighting and of the prompt.
+    pub fn set_theme(&mut self, theme: Theme) -> &mut Self {
+        self.theme = theme;
+        self
+    }
+}
+
+/// start of the word ending at `pos`.
+fn word_start(line: &str, pos: usize) -> usize {
+    line[..pos]
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '!'))
+        .map(|i| i + 1)
+        .unwrap_or(0)
+}
+
+impl Completer for EditHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        Ok(self.complete_input(line, pos))
+    }
 }
 
 impl EditHelper {
-    fn word_matching_color_mode(&self, key_word: &str) -> Option<String> {
-        if self.sql_keyword_list.contains(&key_word.to_lowercase()) {
-            Some(format!("\x1b[1;34m{}\x1b[0m", key_word))
-        } else {
-            None
+    fn complete_input(&self, line: &str, pos: usize) -> (usize, Vec<Pair>) {
+        let command = self
+            .command_completer
+            .as_ref()
+            .and_then(|completer| completer.complete(line, pos));
+        if let Some((start, candidates)) = command {
+            return (start, to_pairs(candidates));
+        }
+
+        let local_context = self
+            .local_completer
+            .as_ref()
+            .and_then(|completer| completer.complete_context(line, pos));
+        if let Some((start, candidates)) = local_context {
+            return (start, to_pairs(candidates));
         }
+
+        let start = word_start(line, pos);
+        let word = line[start..pos].to_lowercase();
+
+        let mut candidates = self
+            .engine_completer
+            .as_ref()
+            .and_then(|completer| completer.suggest(line, pos))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|c| c.to_lowercase().starts_with(&word))
+            .collect::<Vec<_>>();
+
+        if let Some(completer) = &self.local_completer {
+            for name in completer.complete_names(line, pos, &word) {
+                if !candidates.contains(&name) {
+                    candidates.push(name);
+                }
+            }
+        }
+
+        if candidates.is_empty() && !word.is_empty() {
+            candidates = self
+                .sql_keyword_list
+                .iter()
+                .filter(|k| k.starts_with(&word))
+                .cloned()
+                .collect();
+            candidates.sort();
+        }
+
+        (start, to_pairs(candidates))
     }
 }
 
-fn split_with_whitespace(line: &str) -> Option<Vec<&str>> {
-    let mut partition_set = Vec::new();
-    let mut last = 0;
-    for (index, matched) in line.match_indices(|c: char| !(c.is_alphanumeric() || c == '!')) {
-        if last != index {
-            partition_set.push(&line[last..index]);
+fn to_pairs(candidates: Vec<String>) -> Vec<Pair> {
+    candidates
+        .into_iter()
+        .map(|c| Pair {
+            display: c.clone(),
+            replacement: c,
+        })
+        .collect()
+}
+
+/// A hint after the cursor: the rest of a history entry, which Right arrow
+/// accepts, or a note about the input.
+pub enum EditHint {
+    History(String),
+    Note(String),
+}
+
+impl Hint for EditHint {
+    fn display(&self) -> &str {
+        match self {
+            EditHint::History(hint) | EditHint::Note(hint) => hint.as_str(),
         }
-        partition_set.push(matched);
-        last = index + matched.len();
     }
-    if last < line.len() {
-        partition_set.push(&line[last..]);
+
+    fn completion(&self) -> Option<&str> {
+        match self {
+            EditHint::History(hint) => Some(hint.as_str()),
+            EditHint::Note(_) => None,
+        }
     }
+}
+
+impl Hinter for EditHelper {
+    type Hint = EditHint;
 
-    if !partition_set.is_empty() {
-        return Some(partition_set);
-    } else {
-        return None;
+    /// the rest of the current line in the most recent history entry starting
+    /// with the input, only while the cursor is at the end. On an empty line,
+    /// the string which keeps Enter from submitting the statement because its
+    /// `;` is inside.
+    fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<EditHint> {
+        if pos < line.len() || line.trim().is_empty() {
+            return None;
+        }
+        if line.rsplit('\n').next().unwrap_or_default().trim().is_empty() {
+            return unterminated_hint(line).map(EditHint::Note);
+        }
+        ctx.history()
+            .iter()
+            .rev()
+            .find(|entry| entry.len() > line.len() && entry.starts_with(line))
+            .map(|entry| entry[line.len()..].split('\n').next().unwrap_or_default().to_string())
+            .filter(|hint| !hint.is_empty())
+            .map(EditHint::History)
     }
 }
 
 impl Validator for EditHelper {
+    /// Enter inserts a newline until the statement is complete. A bracket left
+    /// unbalanced is pointed out after the submitted statement.
     fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
-        let i = ctx.input();
-        if i.ends_with(";") {
-            Ok(ValidationResult::Valid(None))
-        } else {
-            Ok(ValidationResult::Incomplete)
+        let input = ctx.input();
+        let i = input.trim();
+        if i.is_empty() || (i.starts_with(':') && !i.starts_with(":async")) {
+            return Ok(ValidationResult::Valid(None));
         }
+        if !is_statement_complete(input) {
+            return Ok(ValidationResult::Incomplete);
+        }
+        let script = jobs::async_script(input).unwrap_or(input);
+        let hint = lexer::unbalanced_bracket(script)
+            .map(|bracket| position_hint("unbalanced", bracket.text, script, bracket.start));
+        Ok(ValidationResult::Valid(hint))
     }
 }
 
+/// where the string, comment or block which holds the `;` of `input` starts,
+/// when it isn't closed.
+fn unterminated_hint(input: &str) -> Option<String> {
+    let script = jobs::async_script(input).unwrap_or(input);
+    let token = lexer::unterminated(script).filter(|t| t.text.contains(';'))?;
+    let delimiter = match token.kind {
+        TokenKind::BlockString => &token.text[..3],
+        TokenKind::BlockComment => &token.text[..2],
+        _ => &token.text[..1],
+    };
+    Some(position_hint("unterminated", delimiter, script, token.start))
+}
+
+/// `  -- <what> `<delimiter>` at line L, column C` for the delimiter at `pos`.
+fn position_hint(what: &str, delimiter: &str, script: &str, pos: usize) -> String {
+    let before = &script[..pos];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or_default().chars().count();
+    format!("  -- {} `{}` at line {}, column {}", what, delimiter, line, column)
+}
+
 impl Highlighter for EditHelper {
-    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
         if line.len() <= 1 {
             return Borrowed(line);
         }
 
-        if let Some(partition_set) = split_with_whitespace(line) {
-            let mut replaced_partition_set = Vec::new();
-            for partition in partition_set.iter() {
-                match self.word_matching_color_mode(*partition) {
-                    Some(pat) => {
-                        replaced_partition_set.push(pat);
-                    },
-                    None => {
-                        replaced_partition_set.push(partition.to_string());
-                    }
-                }
+        let mut marks = lexer::matching_delimiter(line, pos)
+            .map(|(at, other)| vec![at, other])
+            .unwrap_or_default();
+        marks.sort_by_key(|m| m.start);
+
+        let mut highlighted = String::with_capacity(line.len() * 2);
+        for token in lexer::tokenize(line) {
+            let is_keyword = token.kind == TokenKind::Word
+                && self.sql_keyword_list.contains(&token.text.to_lowercase());
+            let paint = |text: &str| match self.theme.style_of(token.kind, is_keyword) {
+                Some(style) => style.paint(text),
+                None => text.to_string(),
+            };
+            // the matching delimiters are painted over the style of their token
+            let mut at = token.start;
+            for mark in marks.iter().filter(|m| token.start <= m.start && m.end <= token.end()) {
+                highlighted.push_str(&paint(&line[at..mark.start]));
+                highlighted.push_str(&self.theme.matching.paint(&line[mark.clone()]));
+                at = mark.end;
             }
-            let new_line = replaced_partition_set.join("");
-            return Owned(new_line);
+            highlighted.push_str(&paint(&line[at..token.end()]));
         }
-        
-        Borrowed(line)
+        Owned(highlighted)
+    }
+
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, _default: bool) -> Cow<'b, str> {
+        Owned(self.theme.prompt.paint(prompt))
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Owned(hint.dimmed().to_string())
     }
 
     fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
-        // this function is used for triggering syntax highligher
-        // as currently it is trigger everytime the new character
-        // insert/move and cursor move, return true
+        // the matching bracket moves with the cursor, so the line is
+        // highlighted again on every insert and cursor move
         true
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn point_at_unterminated_strings() {
+        assert_eq!(
+            unterminated_hint("select \"a;\nb\n"),
+            Some(String::from("  -- unterminated `\"` at line 1, column 7"))
+        );
+        assert_eq!(unterminated_hint("select \"a\nb\n"), None);
+        assert_eq!(unterminated_hint("select 1 as a\n"), None);
+    }
+}
@@ -0,0 +1,142 @@
+use std::str::FromStr;
+
+use colored::*;
+
+use crate::utils::conf::ByzerConf;
+use crate::utils::lexer::TokenKind;
+
+/// How a class of tokens is painted, written as a color optionally preceded
+/// by attributes, such as `bold blue`, `italic bright black` or `none`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    color: Option<Color>,
+    bold: bool,
+    dimmed: bool,
+    italic: bool,
+    underline: bool,
+}
+
+impl Style {
+    pub fn paint(&self, text: &str) -> String {
+        if *self == Style::default() {
+            return text.to_string();
+        }
+        let mut painted = text.normal();
+        if let Some(color) = self.color {
+            painted = painted.color(color);
+        }
+        if self.bold {
+            painted = painted.bold();
+        }
+        if self.dimmed {
+            painted = painted.dimmed();
+        }
+        if self.italic {
+            painted = painted.italic();
+        }
+        if self.underline {
+            painted = painted.underline();
+        }
+        painted.to_string()
+    }
+}
+
+impl FromStr for Style {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut style = Style::default();
+        let mut color = vec![];
+        for word in s.split_whitespace() {
+            match word.to_lowercase().as_str() {
+                "none" => {}
+                "bold" => style.bold = true,
+                "dimmed" => style.dimmed = true,
+                "italic" => style.italic = true,
+                "underline" => style.underline = true,
+                _ => color.push(word),
+            }
+        }
+        if !color.is_empty() {
+            style.color = Some(color.join(" ").parse()?);
+        }
+        Ok(style)
+    }
+}
+
+/// Which class of token gets which style in the editor.
+pub struct Theme {
+    pub keyword: Style,
+    pub string: Style,
+    pub number: Style,
+    pub comment: Style,
+    pub path: Style,
+    pub variable: Style,
+    pub command: Style,
+    /// the bracket or quote pairing with the one at the cursor
+    pub matching: Style,
+    pub prompt: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        let style = |s: &str| s.parse().unwrap_or_default();
+        Self {
+            keyword: style("bold blue"),
+            string: style("green"),
+            number: style("cyan"),
+            comment: style("bright black"),
+            path: style("yellow"),
+            variable: style("magenta"),
+            command: style("bold magenta"),
+            matching: style("bold underline"),
+            prompt: style("green"),
+        }
+    }
+}
+
+impl Theme {
+    /// every class can be overridden in the conf file:
+    ///
+    /// ```text
+    /// shell.theme.keyword=bold blue
+    /// shell.theme.string=green
+    /// shell.theme.number=cyan
+    /// shell.theme.comment=bright black
+    /// shell.theme.path=yellow
+    /// shell.theme.variable=magenta
+    /// shell.theme.command=bold magenta
+    /// shell.theme.matching=bold underline
+    /// shell.theme.prompt=green
+    /// ```
+    pub fn new(byzer_conf: &ByzerConf) -> Self {
+        let default = Theme::default();
+        let style = |class: &str, default: Style| byzer_conf.shell_config_or(&format!("theme.{}", class), default);
+        Self {
+            keyword: style("keyword", default.keyword),
+            string: style("string", default.string),
+            number: style("number", default.number),
+            comment: style("comment", default.comment),
+            path: style("path", default.path),
+            variable: style("variable", default.variable),
+            command: style("command", default.command),
+            matching: style("matching", default.matching),
+            prompt: style("prompt", default.prompt),
+        }
+    }
+
+    /// the style of a token of `kind`, `None` for the ones left as is. Words
+    /// are only painted when they are keywords.
+    pub fn style_of(&self, kind: TokenKind, is_keyword: bool) -> Option<&Style> {
+        match kind {
+            TokenKind::Word if is_keyword => Some(&self.keyword),
+            TokenKind::String | TokenKind::BlockString => Some(&self.string),
+            TokenKind::Number => Some(&self.number),
+            TokenKind::LineComment | TokenKind::BlockComment => Some(&self.comment),
+            TokenKind::Backtick => Some(&self.path),
+            TokenKind::Variable => Some(&self.variable),
+            TokenKind::Command => Some(&self.command),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::conf::{open_private, ByzerConf};
+use crate::utils::diagnostic::print_error;
+use crate::utils::engine::http_client;
+use crate::utils::print_as_table;
+
+const REDACTED: &str = "******";
+
+lazy_static! {
+    static ref SECRET_KEY: Regex =
+        Regex::new(r"(?i)password|passwd|secret|token|credential|access[._]?key").unwrap();
+
+    /// `password="xxx"` like options inside a script, the value being a block
+    /// string, a quoted string with escapes, a backtick string or a word
+    static ref SECRET_OPTION: Regex = Regex::new(
+        r#"(?i)(`?[\w.]*(?:password|passwd|secret|token|credential|access[._]?key)[\w.]*`?\s*=\s*)('''[\s\S]*?'''|"(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)*'|`[^`]*`|\S+?)(\s|;|$)"#
+    )
+    .unwrap();
+}
+
+/// whether the value of a parameter or option named `key` must not be logged.
+pub fn is_secret_key(key: &str) -> bool {
+    SECRET_KEY.is_match(key)
+}
+
+/// replace the values of credential options in a script, such as the
+/// `password` of a `connect jdbc` statement.
+pub fn redact_script(script: &str) -> String {
+    SECRET_OPTION
+        .replace_all(script, |captures: &regex::Captures| {
+            let value = &captures[2];
+            let quote = if value.starts_with("'''") {
+                &value[..3]
+            } else if value.starts_with(['"', '\'', '`']) {
+                &value[..1]
+            } else {
+                ""
+            };
+            format!("{}{}{}{}{}", &captures[1], quote, REDACTED, quote, &captures[3])
+        })
+        .to_string()
+}
+
+/// replace the value of a `:var <key> <value>` command, as any variable may
+/// hold a credential. Other commands are kept as they are.
+pub fn redact_command(line: &str) -> String {
+    let mut words = line.trim_start().trim_start_matches(':').split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (Some("var"), Some(key), Some(_)) => format!(":var {} {}", key, REDACTED),
+        _ => line.to_string(),
+    }
+}
+
+/// One request to the engine and what came back, a line of the trace file.
+#[derive(Serialize, Deserialize)]
+pub struct TraceRecord {
+    /// unix time in milliseconds
+    pub time: u64,
+    pub url: String,
+    pub params: HashMap<String, String>,
+    pub status: Option<u16>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(rename = "elapsedMs")]
+    pub elapsed_ms: u64,
+    pub body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl TraceRecord {
+    pub fn new(url: &str, params: &HashMap<&str, &str>, elapsed: Duration) -> Self {
+        let params = params
+            .iter()
+            .map(|(k, v)| {
+                let value = if is_secret_key(k) {
+                    REDACTED.to_string()
+                } else if *k == "sql" {
+                    redact_script(v)
+                } else {
+                    v.to_string()
+                };
+                (k.to_string(), value)
+            })
+            .collect();
+        Self {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            url: url.to_string(),
+            params,
+            status: None,
+            headers: HashMap::new(),
+            elapsed_ms: elapsed.as_millis() as u64,
+            body: None,
+            error: None,
+        }
+    }
+
+    pub fn with_response(mut self, status: u16, headers: &HeaderMap, body: Option<&str>) -> Self {
+        self.status = Some(status);
+        self.headers = headers
+            .iter()
+            .map(|(k, v)| {
+                let value = if is_secret_key(k.as_str()) || k == "set-cookie" {
+                    REDACTED.to_string()
+                } else {
+                    v.to_str().unwrap_or_default().to_string()
+                };
+                (k.to_string(), value)
+            })
+            .collect();
+        self.body = body.map(|b| b.to_string());
+        self
+    }
+
+    pub fn with_error(mut self, error: &str) -> Self {
+        self.error = Some(error.to_string());
+        self
+    }
+}
+
+/// Appends every request sent to the engine to a JSON Lines file, enabled by
+/// `--trace [FILE]` or `:trace on`.
+pub struct HttpTracer {
+    default_path: PathBuf,
+    file: Mutex<Option<File>>,
+}
+
+impl HttpTracer {
+    /// the file defaults to `shell.trace.file`, or `~/.byzer/trace.jsonl`.
+    pub fn new(default_path: PathBuf) -> Self {
+        Self {
+            default_path,
+            file: Mutex::new(None),
+        }
+    }
+
+    pub fn enable(&self, path: Option<&Path>) -> std::io::Result<PathBuf> {
+        let path = path.unwrap_or(self.default_path.as_path()).to_path_buf();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = open_private(OpenOptions::new().append(true), &path)?;
+        *self.file.lock().unwrap() = Some(file);
+        Ok(path)
+    }
+
+    pub fn disable(&self) {
+        *self.file.lock().unwrap() = None;
+    }
+
+    pub fn record(&self, record: TraceRecord) {
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            if let Ok(line) = serde_json::to_string(&record) {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+}
+
+/// send the request recorded on the `line`th line (one based, the last one by
+/// default) of a trace file again, to `url` or to the recorded url.
+/// The timeouts of `shell.http.*` apply.
+pub fn replay(trace: &Path, line: Option<usize>, url: Option<&str>, byzer_conf: &ByzerConf) -> Result<(), String> {
+    let file = File::open(trace).map_err(|e| format!("Fail to open {}: {}", trace.display(), e))?;
+    let lines = BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter(|l| !l.trim().is_empty())
+        .collect::<Vec<_>>();
+    let text = match line {
+        Some(n) => lines.get(n.wrapping_sub(1)),
+        None => lines.last(),
+    }
+    .ok_or_else(|| format!("No such request in {}", trace.display()))?;
+
+    let record = serde_json::from_str::<TraceRecord>(text).map_err(|e| format!("Malformed trace: {}", e))?;
+    if record.params.values().any(|v| v.contains(REDACTED)) {
+        return Err(String::from("The request contains redacted secrets, it can't be sent again."));
+    }
+    let url = url.unwrap_or(record.url.as_str());
+    let script = record.params.get("sql").cloned().unwrap_or_default();
+
+    let resp = http_client(byzer_conf)
+        .post(url)
+        .form(&record.params)
+        .send()
+        .map_err(|e| e.to_string())?;
+    let status = resp.status().as_u16();
+    let body = resp.text().map_err(|e| e.to_string())?;
+    if status == 200 {
+        print_as_table(body.as_str());
+    } else {
+        print_error(script.as_str(), body.as_str(), None);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_secret_options() {
+        let cases = [
+            (
+                "connect jdbc where url=\"jdbc:mysql://h/db\" and password=\"p w\" as db;",
+                "connect jdbc where url=\"jdbc:mysql://h/db\" and password=\"******\" as db;",
+            ),
+            ("select 1 as a where `password`='p\\'w';", "select 1 as a where `password`='******';"),
+            ("set PassWord=p;", "set PassWord=******;"),
+            (
+                "load s3.`b` where accessKey=`a b` and Secret.Key = '''x\ny'''\nas t;",
+                "load s3.`b` where accessKey=`******` and Secret.Key = '''******'''\nas t;",
+            ),
+            ("save a as jdbc.`t` where token=\"a\\\"b\";", "save a as jdbc.`t` where token=\"******\";"),
+            ("select \"tokenizer\" as a;", "select \"tokenizer\" as a;"),
+        ];
+        for (script, expected) in cases {
+            assert_eq!(redact_script(script), expected, "{}", script);
+        }
+    }
+
+    #[test]
+    fn redact_var_values() {
+        assert_eq!(redact_command(":var password s3cr3t"), ":var password ******");
+        assert_eq!(redact_command(":var  db  a b"), ":var db ******");
+        assert_eq!(redact_command(":var db"), ":var db");
+        assert_eq!(redact_command(":history s3cr3t"), ":history s3cr3t");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_trace_file() {
+        use crate::utils::fixtures::TempDir;
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("trace");
+        let tracer = HttpTracer::new(dir.path().join("default.log"));
+        let path = tracer.enable(Some(&dir.path().join("logs/trace.log"))).unwrap();
+        tracer.disable();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Range;
+use std::str::FromStr;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::utils::conf::ByzerConf;
+use crate::utils::lexer::{self, TokenKind};
+use crate::utils::lint::{Lint, Severity};
+use crate::utils::parser::{self, Statement, StatementKind};
+
+lazy_static! {
+    static ref REFERENCE: Regex = Regex::new(r"\$\{\s*([A-Za-z_][\w.]*)\s*\}").unwrap();
+    /// `:key` in a condition, not preceded by a word character or another `:`
+    static ref CONDITION_REFERENCE: Regex = Regex::new(r"(?:^|[^\w:])(:([A-Za-z_][\w]*))").unwrap();
+    static ref KEY: Regex = Regex::new(r"^[A-Za-z_][\w.]*$").unwrap();
+}
+
+/// A reference to a variable: `${key}` anywhere but in comments and `'''`
+/// blocks, which hold code such as python, or `:key` in the condition of `!if`
+/// and `!elif`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference<'a> {
+    pub key: &'a str,
+    pub span: Range<usize>,
+    /// whether it is a `:key` of a condition, whose value is compared as a string
+    pub in_condition: bool,
+}
+
+/// the references to variables of `script`, in order.
+pub fn references(script: &str) -> Vec<Reference<'_>> {
+    let mut references = vec![];
+    for token in lexer::tokenize(script) {
+        if matches!(
+            token.kind,
+            TokenKind::LineComment | TokenKind::BlockComment | TokenKind::BlockString
+        ) {
+            continue;
+        }
+        for captures in REFERENCE.captures_iter(token.text) {
+            let (reference, key) = (captures.get(0).unwrap(), captures.get(1).unwrap());
+            references.push(Reference {
+                key: &script[token.start + key.start()..token.start + key.end()],
+                span: token.start + reference.start()..token.start + reference.end(),
+                in_condition: false,
+            });
+        }
+    }
+
+    let mut conditions = vec![];
+    condition_spans(&parser::parse(script).statements, &mut conditions);
+    for condition in conditions {
+        for captures in CONDITION_REFERENCE.captures_iter(&script[condition.clone()]) {
+            let (reference, key) = (captures.get(1).unwrap(), captures.get(2).unwrap());
+            references.push(Reference {
+                key: &script[condition.start + key.start()..condition.start + key.end()],
+                span: condition.start + reference.start()..condition.start + reference.end(),
+                in_condition: true,
+            });
+        }
+    }
+    references.sort_by_key(|reference| reference.span.start);
+    references
+}
+
+/// the spans of the conditions of the `!if` blocks of `statements`.
+fn condition_spans(statements: &[Statement], spans: &mut Vec<Range<usize>>) {
+    for statement in statements {
+        if let StatementKind::If { branches, .. } = &statement.kind {
+            for branch in branches {
+                spans.extend(branch.condition.as_ref().map(|condition| condition.span.clone()));
+                condition_spans(&branch.body, spans);
+            }
+        }
+    }
+}
+
+/// How the variables of `--var` and `:var` reach the script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VarMode {
+    /// `${key}` is replaced by the value before the script is sent
+    #[default]
+    Substitute,
+    /// `set key="value";` is sent before the script, the engine does the rest
+    Set,
+}
+
+impl FromStr for VarMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "substitute" => Ok(VarMode::Substitute),
+            "set" => Ok(VarMode::Set),
+            _ => Err(format!("unknown variable mode: {}", s)),
+        }
+    }
+}
+
+/// parse `key=value` given to `--var`.
+pub fn parse_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if is_key(key.trim()) => Ok((key.trim().to_string(), value.to_string())),
+        _ => Err(format!("expected key=value, found `{}`", s)),
+    }
+}
+
+fn is_key(key: &str) -> bool {
+    KEY.is_match(key)
+}
+
+/// The local variables of the session, set with `--var key=value` or `:var`,
+/// in the mode of `shell.vars.mode`, `substitute` by default.
+#[derive(Default)]
+pub struct Variables {
+    values: BTreeMap<String, String>,
+    pub mode: VarMode,
+}
+
+impl Variables {
+    pub fn new(byzer_conf: &ByzerConf, vars: &[(String, String)]) -> Self {
+        Self {
+            values: vars.iter().cloned().collect(),
+            mode: byzer_conf.shell_config_or("vars.mode", VarMode::Substitute),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        if !is_key(key) {
+            return Err(format!("invalid variable name `{}`", key));
+        }
+        self.values.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|v| v.as_str())
+    }
+
+    pub fn print(&self) {
+        if self.values.is_empty() {
+            println!("No variables.");
+        }
+        for (key, value) in &self.values {
+            println!("{}={}", key, value);
+        }
+    }
+
+    /// the script to send for `script`, or the references to variables which
+    /// are neither local nor set by the script itself.
+    pub fn apply(&self, script: &str) -> Result<String, Vec<Lint>> {
+        let mut defined = HashSet::new();
+        set_keys(&parser::parse(script).statements, &mut defined);
+
+        let mut missing = vec![];
+        let mut used = vec![];
+        let mut out = String::with_capacity(script.len());
+        let mut last = 0;
+        for reference in references(script) {
+            let key = reference.key;
+            match self.values.get(key) {
+                Some(value) if !defined.contains(key) => {
+                    if !used.contains(&key) {
+                        used.push(key);
+                    }
+                    if self.mode == VarMode::Substitute {
+                        out.push_str(&script[last..reference.span.start]);
+                        if reference.in_condition {
+                            out.push_str(&condition_literal(script, reference.span.start, value));
+                        } else {
+                            out.push_str(value);
+                        }
+                        last = reference.span.end;
+                    }
+                }
+                Some(_) => {}
+                None if defined.contains(key) => {}
+                None => missing.push(Lint {
+                    severity: Severity::Error,
+                    rule: "undefined-variable",
+                    message: format!("variable `{}` is not defined, `:var {} <value>` to define it", key, key),
+                    span: reference.span,
+                }),
+            }
+        }
+        out.push_str(&script[last..]);
+
+        if !missing.is_empty() {
+            return Err(missing);
+        }
+        if self.mode == VarMode::Set && !used.is_empty() {
+            let sets = used
+                .iter()
+                .map(|key| format!("set {}=\"{}\";\n", key, escape(&self.values[*key])))
+                .collect::<String>();
+            return Ok(format!("{}{}", sets, script));
+        }
+        Ok(out)
+    }
+}
+
+/// the variables set by the `set` statements of a script, which win over the
+/// local ones.
+fn set_keys(statements: &[Statement], keys: &mut HashSet<String>) {
+    for statement in statements {
+        match &statement.kind {
+            StatementKind::Set { key, .. } => {
+                keys.insert(key.node.clone());
+            }
+            StatementKind::If { branches, .. } => {
+                for branch in branches {
+                    set_keys(&branch.body, keys);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// `value` as a string literal of the condition holding `pos`, escaped again
+/// when the condition itself is a double quoted string.
+fn condition_literal(script: &str, pos: usize, value: &str) -> String {
+    let literal = format!("\"{}\"", escape(value));
+    let condition = lexer::tokenize(script).into_iter().find(|t| t.start <= pos && pos < t.end());
+    match condition {
+        Some(token) if token.text.starts_with('"') => escape(&literal),
+        _ => literal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables(mode: VarMode) -> Variables {
+        Variables {
+            values: [("day", "2021-01-01"), ("path", "/tmp/\"a\"")]
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            mode,
+        }
+    }
+
+    #[test]
+    fn substitute_variables() {
+        let script = "set table = \"t\";\n-- ${day} stays\nload csv.`${path}` where day=\"${ day }\" as ${table};";
+        assert_eq!(
+            variables(VarMode::Substitute).apply(script).unwrap(),
+            "set table = \"t\";\n-- ${day} stays\nload csv.`/tmp/\"a\"` where day=\"2021-01-01\" as ${table};"
+        );
+        assert_eq!(
+            variables(VarMode::Set).apply(script).unwrap(),
+            format!("set path=\"/tmp/\\\"a\\\"\";\nset day=\"2021-01-01\";\n{}", script)
+        );
+    }
+
+    #[test]
+    fn report_missing_variables() {
+        let script = "select \"${day}\" as a;\nselect ${month} as b;";
+        let missing = variables(VarMode::Substitute).apply(script).unwrap_err();
+        assert_eq!(missing.len(), 1);
+        assert_eq!(&script[missing[0].span.clone()], "${month}");
+    }
+
+    #[test]
+    fn skip_block_strings() {
+        let script = "run command as Ray.`` where code='''\nprint(f\"${day} ${x}\")\n'''\nand day=\"${day}\" as t;";
+        assert_eq!(references(script).iter().map(|r| r.key).collect::<Vec<_>>(), vec!["day"]);
+        assert_eq!(
+            variables(VarMode::Substitute).apply(script).unwrap(),
+            script.replace("\"${day}\"", "\"2021-01-01\"")
+        );
+    }
+
+    #[test]
+    fn condition_references() {
+        let script = "!if ''':day == \"2021-01-01\" and :month::int > 1''';\nselect \"a:day\" as a;\n!fi;";
+        let keys = references(script).iter().map(|r| (r.key, r.in_condition)).collect::<Vec<_>>();
+        assert_eq!(keys, vec![("day", true), ("month", true)]);
+
+        let mut vars = variables(VarMode::Substitute);
+        assert_eq!(&script[vars.apply(script).unwrap_err()[0].span.clone()], ":month");
+        vars.set("month", "2").unwrap();
+        assert_eq!(
+            vars.apply(script).unwrap(),
+            "!if '''\"2021-01-01\" == \"2021-01-01\" and \"2\"::int > 1''';\nselect \"a:day\" as a;\n!fi;"
+        );
+        assert_eq!(
+            vars.apply("!if \":day == 'x'\";\n!fi;").unwrap(),
+            "!if \"\\\"2021-01-01\\\" == 'x'\";\n!fi;"
+        );
+
+        vars.mode = VarMode::Set;
+        assert!(vars.apply(script).unwrap().starts_with("set day=\"2021-01-01\";\nset month=\"2\";\n!if"));
+    }
+
+    #[test]
+    fn parse_vars() {
+        assert_eq!(parse_var("day=2021-01-01"), Ok((String::from("day"), String::from("2021-01-01"))));
+        assert_eq!(parse_var("a.b=x=y"), Ok((String::from("a.b"), String::from("x=y"))));
+        assert!(parse_var("day").is_err());
+        assert!(parse_var("1a=b").is_err());
+    }
+}